@@ -1,17 +1,47 @@
 defmodule HUBT do
   @zero_hash :binary.copy(<<0>>, 32)
 
-  def init() do
+  # :legacy hashes leaves as sha256(k <> v) and nodes as sha256(l <> r).
+  # :hardened is sha256(<<0, len(k)::32, k, len(v)::32, v>>) and sha256(<<1, l, r>>),
+  # the same as Rust's `Hardened<Sha256Hasher>`. Roots differ, pick one per tree.
+  # :hardened parents and the root also take each node bound to its position, see bind_hash/3.
+  def init(hash_mode \\ :legacy) when hash_mode in [:legacy, :hardened] do
+    :persistent_term.put({HUBT, :hash_mode}, hash_mode)
     :ets.new(THUBT, [
       :ordered_set, :named_table, :public,
       {:write_concurrency, true}, {:read_concurrency, true}
     ])
   end
 
+  def hash_mode(), do: :persistent_term.get({HUBT, :hash_mode}, :legacy)
+
+  def leaf_hash(k, v) do
+    case hash_mode() do
+      :hardened -> :crypto.hash(:sha256, <<0, byte_size(k)::32, k::binary, byte_size(v)::32, v::binary>>)
+      :legacy -> :crypto.hash(:sha256, k <> v)
+    end
+  end
+
+  def node_hash(l, r) do
+    case hash_mode() do
+      :hardened -> :crypto.hash(:sha256, <<1, l::binary, r::binary>>)
+      :legacy -> :crypto.hash(:sha256, l <> r)
+    end
+  end
+
+  # sha256(<<2, len::16, path, hash>>) when hardened, path zero after len. :legacy binds
+  # nothing and keeps the node's hash. Rust's `TreeHasher::bind`.
+  def bind_hash(len, path, hash) do
+    case hash_mode() do
+      :hardened -> :crypto.hash(:sha256, <<2, len::16, path::binary, hash::binary>>)
+      :legacy -> hash
+    end
+  end
+
   def root() do
     case :ets.first(THUBT) do
       :"$end_of_table" -> @zero_hash
-      key -> [{_, hash}] = :ets.lookup(THUBT, key); hash
+      {:n, path, len} = key -> [{_, hash}] = :ets.lookup(THUBT, key); bind_hash(len, path, hash)
     end
   end
 
@@ -19,7 +49,7 @@ defmodule HUBT do
   def batch_update(ops) do
     prepared_ops = ops
     |> Enum.map(fn
-      {:insert, k, v} -> {:insert, :crypto.hash(:sha256, k), :crypto.hash(:sha256, k <> v)}
+      {:insert, k, v} -> {:insert, :crypto.hash(:sha256, k), leaf_hash(k, v)}
       {:delete, k, _} -> {:delete, :crypto.hash(:sha256, k)}
     end)
     |> Enum.sort_by(fn {_, p, _} -> p; {_, p} -> p end)
@@ -63,7 +93,7 @@ defmodule HUBT do
 
   defp check_neighbor({{_, n_path, 256}, [{_, n_leaf}]}, path, leaf) do
     {lcp_path, len} = lcp(path, n_path)
-    :ets.insert(THUBT, {{:n, lcp_path, len}, :crypto.hash(:sha256, leaf <> n_leaf)})
+    :ets.insert(THUBT, {{:n, lcp_path, len}, node_hash(leaf, n_leaf)})
   end
   defp check_neighbor(_, _, _), do: :ok
 
@@ -77,7 +107,7 @@ defmodule HUBT do
 
       # CANONICAL LOGIC: Node exists ONLY if it branches (both children exist)
       if l_hash != @zero_hash and r_hash != @zero_hash do
-        :ets.insert(THUBT, {{:n, path, len}, :crypto.hash(:sha256, l_hash <> r_hash)})
+        :ets.insert(THUBT, {{:n, path, len}, node_hash(l_hash, r_hash)})
       else
         :ets.delete(THUBT, {:n, path, len})
       end
@@ -113,16 +143,17 @@ defmodule HUBT do
     end
   end
 
+  # Bound to the child's position, as the parent commits to it
   defp get_child_hash(p_path, p_len, dir) do
     <<prefix::bitstring-size(p_len), _::bitstring>> = p_path
     target = <<prefix::bitstring, dir::1, 0::size(255 - p_len)>>
     t_len = p_len + 1
     
     case closest_or_next_lookup(target, t_len) do
-      {{:n, fp, _}, hash} ->
+      {{:n, fp, f_len}, hash} ->
         <<t_pre::size(t_len), _::bitstring>> = target
         <<f_pre::size(t_len), _::bitstring>> = fp
-        if t_pre == f_pre, do: hash, else: @zero_hash
+        if t_pre == f_pre, do: bind_hash(f_len, fp, hash), else: @zero_hash
       _ -> @zero_hash
     end
   end
@@ -149,7 +180,7 @@ defmodule HUBT do
   # 1. INCLUSION PROOF
   def prove(k, v) do
     path = :crypto.hash(:sha256, k)
-    leaf = :crypto.hash(:sha256, k <> v)
+    leaf = leaf_hash(k, v)
 
     case :ets.lookup(THUBT, {:n, path, 256}) do
       [{_, val}] when val == leaf ->
@@ -159,8 +190,8 @@ defmodule HUBT do
   end
 
   def verify(k, v, proof) do
-    leaf = :crypto.hash(:sha256, k <> v)
-    calculate_root(leaf, proof.nodes) == proof.root
+    leaf = leaf_hash(k, v)
+    calculate_root(:crypto.hash(:sha256, k), 256, leaf, proof.nodes) == proof.root
   end
 
   # 2. EXCLUSION PROOF (Divergence)
@@ -177,6 +208,7 @@ defmodule HUBT do
            %{
              type: :non_existence,
              proven_path: path,
+             proven_len: len,
              proven_hash: best_hash,
              proof: %{root: root(), nodes: generate_proof_nodes(path, len)}
            }
@@ -184,20 +216,15 @@ defmodule HUBT do
     end
   end
 
+  # The checks of verify_proof/3, the divergence below the proven length included
   def verify_non_existence(k, proof) do
-    target = :crypto.hash(:sha256, k)
-    if proof.proof.root == @zero_hash do
-      proof.proof.nodes == []
-    else
-      # Integrity: Reconstruct root from Proven Hash
-      root_ok = (calculate_root(proof.proven_hash, proof.proof.nodes) == proof.proof.root)
-
-      # Logic: Divergence & Ambiguity Check
-      div_idx = do_divergence_index(proof.proven_path, target, 0)
-      ambiguous = Enum.any?(proof.proof.nodes, fn node -> node.len == div_idx end)
-      
-      root_ok and (proof.proven_path != target) and (not ambiguous)
-    end
+    p = Map.get(proof, :proof, %{})
+    unified = %{
+      root: Map.get(p, :root), nodes: Map.get(p, :nodes),
+      path: Map.get(proof, :proven_path, @zero_hash), len: Map.get(proof, :proven_len, 0),
+      hash: Map.get(proof, :proven_hash, @zero_hash), value: nil
+    }
+    verify_proof(unified, k, "") == :non_existence
   end
 
   # 3. MISMATCH PROOF (Key exists, value differs)
@@ -206,23 +233,117 @@ defmodule HUBT do
     case :ets.lookup(THUBT, {:n, path, 256}) do
       [] -> %{error: :key_not_found}
       [{_, actual}] ->
-        if actual == :crypto.hash(:sha256, k <> v_claimed), do: %{error: :value_matches}, else:
+        if actual == leaf_hash(k, v_claimed), do: %{error: :value_matches}, else:
         %{
           type: :mismatch,
           actual_hash: actual,
-          claimed_hash: :crypto.hash(:sha256, k <> v_claimed),
+          claimed_hash: leaf_hash(k, v_claimed),
           proof: %{root: root(), nodes: generate_proof_nodes(path, 256)}
         }
     end
   end
 
   def verify_mismatch(k, v_claimed, proof) do
-    calc_claimed = :crypto.hash(:sha256, k <> v_claimed)
+    calc_claimed = leaf_hash(k, v_claimed)
     (proof.type == :mismatch) and
     (proof.actual_hash != calc_claimed) and
-    (calculate_root(proof.actual_hash, proof.proof.nodes) == proof.proof.root)
+    (calculate_root(:crypto.hash(:sha256, k), 256, proof.actual_hash, proof.proof.nodes) == proof.proof.root)
   end
   
+  # 4. UNIFIED PROOF
+  # The shape of Rust's `Proof`: the node k's path ends on and its siblings up to the root,
+  # each with the length of the ancestor branching into it. One proof for all three cases,
+  # verify_proof/3 tells which one holds. encode_proof/1 gives the bytes of Rust's `Wire`.
+  def prove(k) do
+    case find_longest_prefix_node(:crypto.hash(:sha256, k)) do
+      {nil, _} -> %{root: @zero_hash, nodes: [], path: @zero_hash, hash: @zero_hash, len: 0, value: nil}
+      {{:n, path, len}, hash} ->
+        %{root: root(), nodes: generate_proof_nodes(path, len), path: path, hash: hash, len: len, value: nil}
+    end
+  end
+
+  def verify_proof(proof, k, v) when is_binary(k) and is_binary(v) do
+    target = :crypto.hash(:sha256, k)
+    cond do
+      not proof_structure?(proof) -> :invalid
+      proof.root == @zero_hash ->
+        if proof.nodes == [] and proof.hash == @zero_hash, do: :non_existence, else: :invalid
+      calculate_root(proof.path, proof.len, proof.hash, proof.nodes) != proof.root -> :invalid
+      proof.path == target and proof.len == 256 ->
+        if proof.hash == leaf_hash(k, v), do: :included, else: :mismatch
+      true ->
+        div_idx = do_divergence_index(target, proof.path, 0)
+        ambiguous = Enum.any?(proof.nodes, fn node -> node.len == div_idx end)
+        if div_idx < proof.len and not ambiguous, do: :non_existence, else: :invalid
+    end
+  end
+
+  def verify_proof(_, _, _), do: :invalid
+
+  # Path is zero after len, ancestor lengths shrink towards the root and each sibling
+  # sits on the other side of the path. Anything else, maps or lists of the wrong shape
+  # included, is not a proof.
+  defp proof_structure?(%{root: root, path: path, hash: hash, len: len, nodes: nodes})
+       when is_integer(len) and len in 0..256 and is_list(nodes) do
+    if hash?(root) and hash?(path) and hash?(hash) do
+      <<prefix::bitstring-size(len), _::bitstring>> = path
+      pad_to_256(prefix) == path and
+        Enum.reduce_while(nodes, len, fn
+          %{hash: h, direction: d, len: l}, below when is_integer(l) and l in 0..255 and l < below and d in [0, 1] ->
+            <<_::size(l), bit::1, _::bitstring>> = path
+            if hash?(h) and d == 1 - bit, do: {:cont, l}, else: {:halt, false}
+          _, _ ->
+            {:halt, false}
+        end) != false
+    else
+      false
+    end
+  end
+  defp proof_structure?(_), do: false
+
+  @wire_version 1
+  @proof_fields ["hash", "kind", "len", "nodes", "path", "root", "value", "version"]
+  @node_fields ["direction", "hash", "len"]
+
+  def encode_proof(proof) do
+    VecPak.encode(%{
+      version: @wire_version, kind: "proof", root: proof.root, path: proof.path,
+      hash: proof.hash, len: proof.len, value: proof.value,
+      nodes: Enum.map(proof.nodes, &Map.take(&1, [:hash, :direction, :len]))
+    })
+  end
+
+  # Strict, like Rust's `Wire::decode`: exact fields and sizes, and the input must be
+  # the bytes encode_proof/1 writes for the result.
+  def decode_proof(bin) do
+    with %{} = m <- (try do VecPak.decode(bin) rescue _ -> nil end),
+         m = string_keys(m),
+         @proof_fields <- Enum.sort(Map.keys(m)),
+         %{"version" => @wire_version, "kind" => "proof", "nodes" => nodes} when is_list(nodes) <- m,
+         nodes = Enum.map(nodes, fn %{} = node -> decode_proof_node(string_keys(node)); _ -> nil end),
+         false <- Enum.member?(nodes, nil),
+         proof = %{root: m["root"], nodes: nodes, path: m["path"], hash: m["hash"], len: m["len"], value: m["value"]},
+         true <- hash?(proof.root) and hash?(proof.path) and hash?(proof.hash),
+         true <- is_integer(proof.len) and proof.len in 0..256,
+         true <- is_nil(proof.value) or is_binary(proof.value),
+         ^bin <- encode_proof(proof) do
+      {:ok, proof}
+    else
+      _ -> {:error, :invalid_encoding}
+    end
+  end
+
+  defp decode_proof_node(%{"hash" => hash, "direction" => d, "len" => len} = node) do
+    if Enum.sort(Map.keys(node)) == @node_fields and hash?(hash) and d in [0, 1] and is_integer(len) and len in 0..256,
+      do: %{hash: hash, direction: d, len: len}
+  end
+  defp decode_proof_node(_), do: nil
+
+  defp hash?(h), do: is_binary(h) and byte_size(h) == 32
+
+  # VecPak decodes a map key to an atom when that atom already exists
+  defp string_keys(m), do: Map.new(m, fn {k, v} when is_atom(k) -> {Atom.to_string(k), v}; kv -> kv end)
+
   # --- PROOF HELPERS (Unified) ---
   defp generate_proof_nodes(path, len) do
     changes_path_from_lcp(path, len - 1, [])
@@ -236,9 +357,12 @@ defmodule HUBT do
     end)
   end
 
-  defp calculate_root(leaf, nodes) do
-    Enum.reduce(nodes, leaf, fn %{hash: h, direction: d}, acc ->
-      if d == 0, do: :crypto.hash(:sha256, h <> acc), else: :crypto.hash(:sha256, acc <> h)
+  # Binds the found node and each ancestor to `path` and their lengths
+  defp calculate_root(path, len, hash, nodes) do
+    Enum.reduce(nodes, bind_hash(len, path, hash), fn %{hash: h, direction: d, len: l}, acc ->
+      <<prefix::bitstring-size(l), _::bitstring>> = path
+      node = if d == 0, do: node_hash(h, acc), else: node_hash(acc, h)
+      bind_hash(l, pad_to_256(prefix), node)
     end)
   end
 
@@ -0,0 +1,27 @@
+defmodule HUBT.MixProject do
+  use Mix.Project
+
+  # Only here to test hubt.ex against the shared fixtures with VecPak loaded:
+  #
+  #   cd bintree/bindings/ex && mix deps.get && mix test
+  def project do
+    [
+      app: :hubt,
+      version: "0.1.0",
+      elixir: "~> 1.19",
+      elixirc_paths: ["hubt.ex"],
+      deps: deps()
+    ]
+  end
+
+  def application do
+    [extra_applications: [:crypto]]
+  end
+
+  defp deps do
+    [
+      {:vecpak_ex_nif, path: "../../../vecpak/bindings/ex/nif"},
+      {:rustler, ">= 0.36.1", runtime: false}
+    ]
+  end
+end
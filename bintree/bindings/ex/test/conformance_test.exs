@@ -0,0 +1,82 @@
+# Runs the shared proof conformance cases (fixtures/conformance.txt) against HUBT
+defmodule HUBT.ConformanceTest do
+  use ExUnit.Case, async: false
+
+  @fixture Path.expand("../../../fixtures/conformance.txt", __DIR__)
+
+  defp cases(mode) do
+    File.read!(@fixture)
+    |> String.split("\n", trim: true)
+    |> Enum.reject(&String.starts_with?(&1, "#"))
+    |> Enum.map(fn line ->
+      [mode, status, k, v, proof] = String.split(line, " ")
+      bin = fn "-" -> ""; hex -> Base.decode16!(hex, case: :lower) end
+      %{line: line, mode: String.to_atom(mode), status: String.to_atom(status), k: bin.(k), v: bin.(v), proof: bin.(proof)}
+    end)
+    |> Enum.filter(&(&1.mode == mode))
+  end
+
+  defp tree(mode) do
+    if :ets.whereis(THUBT) != :undefined, do: :ets.delete(THUBT)
+    HUBT.init(mode)
+    HUBT.batch_update(for i <- 0..15, do: {:insert, <<i::32>>, "v#{i}"})
+  end
+
+  for mode <- [:legacy, :hardened] do
+    test "#{mode} cases" do
+      mode = unquote(mode)
+      tree(mode)
+      assert cases(mode) != []
+
+      for c <- cases(mode) do
+        assert {:ok, proof} = HUBT.decode_proof(c.proof), c.line
+        assert {:ok, ^proof} = proof |> HUBT.encode_proof() |> HUBT.decode_proof()
+        assert HUBT.verify_proof(proof, c.k, c.v) == c.status, c.line
+
+        # Forged cases aside, a proof made here reaches the same verdict on the same root
+        own = HUBT.prove(c.k)
+        assert own.root == proof.root, c.line
+        {:ok, own} = own |> HUBT.encode_proof() |> HUBT.decode_proof()
+        if c.status != :invalid, do: assert(HUBT.verify_proof(own, c.k, c.v) == c.status, c.line)
+      end
+    end
+  end
+
+  test "malformed proofs are invalid" do
+    tree(:hardened)
+    proof = HUBT.prove(<<99::32>>)
+    assert HUBT.verify_proof(proof, <<99::32>>, "") == :non_existence
+    assert proof.nodes != []
+
+    for bad <- [
+          nil, [], %{}, "proof", Map.delete(proof, :nodes), %{proof | nodes: %{}},
+          %{proof | nodes: [nil]}, %{proof | nodes: [[1, 2]]}, %{proof | len: -1}, %{proof | len: 257},
+          %{proof | len: "8"}, %{proof | path: <<1>>}, %{proof | root: nil}, %{proof | hash: 7},
+          %{proof | nodes: Enum.map(proof.nodes, &%{&1 | len: 300})},
+          %{proof | nodes: Enum.map(proof.nodes, &%{&1 | direction: 2})},
+          %{proof | nodes: Enum.map(proof.nodes, &Map.delete(&1, :hash))}
+        ] do
+      assert HUBT.verify_proof(bad, <<99::32>>, "") == :invalid, inspect(bad)
+    end
+    assert HUBT.verify_proof(proof, nil, "") == :invalid
+    assert HUBT.verify_proof(proof, <<99::32>>, nil) == :invalid
+  end
+
+  test "verify_non_existence checks the divergence against the proven length" do
+    tree(:legacy)
+    k = <<99::32>>
+    proof = HUBT.prove_non_existence(k)
+    assert HUBT.verify_non_existence(k, proof)
+
+    # A present key's own leaf claims its absence
+    present = <<3::32>>
+    %{root: root, nodes: nodes, path: path, hash: hash, len: len} = HUBT.prove(present)
+    forged = %{proven_path: path, proven_len: len, proven_hash: hash, proof: %{root: root, nodes: nodes}}
+    refute HUBT.verify_non_existence(present, forged)
+
+    # The root node, an ancestor of every present key, claimed as where k's path ends
+    {:n, rpath, rlen} = key = :ets.first(THUBT)
+    forged = %{proven_path: rpath, proven_len: rlen, proven_hash: :ets.lookup_element(THUBT, key, 2), proof: %{root: HUBT.root(), nodes: []}}
+    refute HUBT.verify_non_existence(present, forged)
+  end
+end
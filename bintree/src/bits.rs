@@ -0,0 +1,2 @@
+pub use hubt_verifier::bits::Path;
+pub(crate) use hubt_verifier::bits::{get_bit_be, lcp_be, mask_after_be, prefix_match_be, set_bit_be};
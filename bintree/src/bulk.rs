@@ -0,0 +1,152 @@
+use crate::bits::{lcp_be, Path};
+use crate::error::{HubtError, Result};
+use crate::hasher::{Hash, KeyMode, TreeHasher, ZERO_HASH};
+use crate::store::{HubtStore, NodeKey};
+use crate::tree::Tree;
+use rayon::prelude::*;
+
+/// A leaf of a bulk load with its original key and value.
+pub(crate) type BulkLeaf = (Path, Hash, (Vec<u8>, Vec<u8>));
+
+/// Hashes `kvs` into leaves sorted by path, a key given twice keeps its last value.
+pub(crate) fn sorted_leaves<H: TreeHasher>(mode: KeyMode, kvs: Vec<(Vec<u8>, Vec<u8>)>) -> Vec<BulkLeaf> {
+    let mut leaves: Vec<BulkLeaf> =
+        kvs.into_par_iter().map(|(k, v)| (mode.path::<H>(&k), H::leaf(&k, &v), (k, v))).collect();
+    // Stable, so duplicates stay in input order and the last one is kept
+    leaves.par_sort_by_key(|leaf| leaf.0);
+    let mut distinct: Vec<BulkLeaf> = Vec::with_capacity(leaves.len());
+    for leaf in leaves {
+        match distinct.last_mut() {
+            Some(last) if last.0 == leaf.0 => *last = leaf,
+            _ => distinct.push(leaf),
+        }
+    }
+    distinct
+}
+
+/// Computes every internal node over `leaves` (sorted by path, distinct) in one pass and returns the root.
+///
+/// Branch nodes are the LCPs of adjacent leaves. The stack holds the finished subtrees on the
+/// right edge, a subtree is merged into its left neighbour once the next leaf branches off above
+/// both, so `emit` sees every node after its children.
+pub fn internal_nodes<H: TreeHasher>(
+    leaves: impl IntoIterator<Item = (Path, Hash)>,
+    mut emit: impl FnMut(NodeKey, Hash) -> Result<()>,
+) -> Result<Hash> {
+    let mut edge = Vec::new();
+    for (path, hash) in leaves {
+        push_leaf::<H>(&mut edge, path, hash, &mut emit)?;
+    }
+    finish_edge::<H>(&mut edge, &mut emit)
+}
+
+fn merge<H: TreeHasher>(
+    edge: &mut Vec<(NodeKey, Hash)>,
+    (right, r_hash): (NodeKey, Hash),
+    emit: &mut impl FnMut(NodeKey, Hash) -> Result<()>,
+) -> Result<(NodeKey, Hash)> {
+    let (left, l_hash) = edge.pop().expect("merge needs a left subtree");
+    let (path, len) = lcp_be(&left.path, &right.path);
+    let (l_bound, r_bound) = (H::bind(left.len, &left.path, &l_hash), H::bind(right.len, &right.path, &r_hash));
+    let node = (NodeKey { path, len }, H::node(&l_bound, &r_bound));
+    emit(node.0, node.1)?;
+    Ok(node)
+}
+
+/// Adds the next leaf to `edge`, the right edge of a tree built as in `internal_nodes`.
+pub(crate) fn push_leaf<H: TreeHasher>(
+    edge: &mut Vec<(NodeKey, Hash)>,
+    path: Path,
+    hash: Hash,
+    emit: &mut impl FnMut(NodeKey, Hash) -> Result<()>,
+) -> Result<()> {
+    if let Some(&(prev, _)) = edge.last() {
+        if path <= prev.path {
+            return Err(HubtError::UnsortedLeaves(path.to_vec()));
+        }
+        let (_, branch) = lcp_be(&prev.path, &path);
+        let mut top = edge.pop().expect("edge is not empty");
+        while edge.last().is_some_and(|(left, _)| lcp_be(&left.path, &top.0.path).1 > branch) {
+            top = merge::<H>(edge, top, emit)?;
+        }
+        edge.push(top);
+    }
+    edge.push((NodeKey { path, len: 256 }, hash));
+    Ok(())
+}
+
+/// Merges what is left on `edge` up to the root.
+pub(crate) fn finish_edge<H: TreeHasher>(
+    edge: &mut Vec<(NodeKey, Hash)>,
+    emit: &mut impl FnMut(NodeKey, Hash) -> Result<()>,
+) -> Result<Hash> {
+    let mut top = match edge.pop() {
+        Some(top) => top,
+        None => return Ok(ZERO_HASH),
+    };
+    while !edge.is_empty() {
+        top = merge::<H>(edge, top, emit)?;
+    }
+    Ok(H::bind(top.0.len, &top.0.path, &top.1))
+}
+
+impl<S: HubtStore, H: TreeHasher> Tree<S, H> {
+    /// Fills an empty tree with `kvs` in one pass, skipping the split points and dirty set of
+    /// `batch_update`. A key given twice keeps its last value. Returns the root.
+    pub fn bulk_load(&mut self, kvs: Vec<(Vec<u8>, Vec<u8>)>) -> Result<Hash> {
+        if self.root()? != ZERO_HASH {
+            return Err(HubtError::NotEmpty);
+        }
+        self.store.start_batch()?;
+        let leaves = sorted_leaves::<H>(self.mode(), kvs);
+        for (path, hash, (k, v)) in &leaves {
+            self.store.put(NodeKey { path: *path, len: 256 }, *hash)?;
+            self.store.put_value(*path, k, v)?;
+        }
+        let store = &mut self.store;
+        internal_nodes::<H>(leaves.iter().map(|leaf| (leaf.0, leaf.1)), |key, hash| store.put(key, hash))
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hubt_mem::{Hubt, ValueHubt};
+    use crate::test_util::key;
+    use crate::tree::Op;
+
+    fn kv(i: u32, v: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        (key(i), v.to_vec())
+    }
+
+    #[test]
+    fn test_bulk_load_matches_batch_update() {
+        for n in [0u32, 1, 2, 3, 1000, 50_000] {
+            let kvs: Vec<_> = (0..n).map(|i| kv(i, b"v")).collect();
+            let mut bulk = Hubt::new();
+            let root = bulk.bulk_load(kvs.clone()).unwrap();
+            let mut incremental = Hubt::new();
+            incremental.batch_update(kvs.into_iter().map(|(k, v)| Op::Insert(k, v)).collect()).unwrap();
+
+            assert_eq!(root, incremental.root().unwrap());
+            assert!(bulk.store == incremental.store);
+        }
+
+        // Values are stored, the last duplicate wins and the tree keeps working incrementally
+        let mut hubt = ValueHubt::new();
+        hubt.bulk_load(vec![kv(1, b"a"), kv(2, b"b"), kv(1, b"c")]).unwrap();
+        assert_eq!(hubt.get(&1u32.to_be_bytes()).unwrap(), Some(b"c".to_vec()));
+        hubt.batch_update(vec![Op::Insert(key(3), b"d".to_vec())]).unwrap();
+        let mut expected = ValueHubt::new();
+        expected.batch_update([kv(1, b"c"), kv(2, b"b"), kv(3, b"d")].into_iter().map(|(k, v)| Op::Insert(k, v)).collect()).unwrap();
+        assert_eq!(hubt.root().unwrap(), expected.root().unwrap());
+
+        assert_eq!(hubt.bulk_load(vec![kv(9, b"v")]), Err(HubtError::NotEmpty));
+        let unsorted = [([2u8; 32], ZERO_HASH), ([1u8; 32], ZERO_HASH)];
+        assert_eq!(internal_nodes::<crate::hasher::Sha256Hasher>(unsorted, |_, _| Ok(())), Err(HubtError::UnsortedLeaves(vec![1u8; 32])));
+    }
+}
@@ -0,0 +1,159 @@
+use crate::bits::{get_bit_be, prefix_match_be};
+use crate::error::Result;
+use crate::hasher::{Hash, KeyMode, TreeHasher, ZERO_HASH};
+use crate::proof::{verify_structure, Proof, ProofNode};
+use crate::store::{HubtRead, NodeKey};
+use crate::tree::{child, Tree};
+use std::collections::BTreeMap;
+
+/// What a batch changed, enough to bring a `Proof` up to the new root, see `ChangeSet::update_proof`.
+///
+/// `nodes` are the root node, every node the batch wrote and both children of the written
+/// internal nodes, sorted. A node without children in the set heads a subtree the batch did not touch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChangeSet {
+    pub root: Hash,
+    pub nodes: Vec<(NodeKey, Hash)>,
+}
+
+impl<S: HubtRead, H: TreeHasher> Tree<S, H> {
+    /// Change set of the batch that returned `changed` (`BatchResult::changed`), read right after it.
+    pub fn change_set(&self, changed: &[NodeKey]) -> Result<ChangeSet> {
+        let mut nodes = BTreeMap::new();
+        if let Some((key, hash)) = self.store.seek_next(&NodeKey { path: [0u8; 32], len: 0 })? {
+            nodes.insert(key, hash);
+        }
+        for key in changed {
+            let hash = match self.store.get(key)? {
+                Some(hash) => hash,
+                None => continue,
+            };
+            nodes.insert(*key, hash);
+            if key.len < 256 {
+                for dir in 0..2 {
+                    nodes.extend(self.get_child(key.path, key.len, dir)?);
+                }
+            }
+        }
+        Ok(ChangeSet { root: self.root()?, nodes: nodes.into_iter().collect() })
+    }
+}
+
+impl ChangeSet {
+    /// The proof of `k` against `self.root`, from `proof` of `k` before the batch.
+    ///
+    /// Walks the new tree down `k`'s path through the changed nodes and keeps the part of `proof`
+    /// below the first subtree the batch did not touch. `None` if the proof and the change set do
+    /// not hold together. The value is kept while the leaf is unchanged.
+    pub fn update_proof<H: TreeHasher>(&self, proof: &Proof, mode: KeyMode, k: &[u8]) -> Option<Proof> {
+        let (changes, old) = (self, proof);
+        if !old.verify_integrity::<H>() {
+            return None;
+        }
+        let empty = Proof { root: ZERO_HASH, nodes: vec![], path: ZERO_HASH, hash: ZERO_HASH, len: 0, value: None };
+        if changes.root == ZERO_HASH {
+            return changes.nodes.is_empty().then_some(empty);
+        }
+        let target = mode.path::<H>(k);
+        let view: BTreeMap<NodeKey, Hash> = changes.nodes.iter().copied().collect();
+        let (mut at, mut at_hash) = view.first_key_value().map(|(key, hash)| (*key, *hash))?;
+
+        // Changed nodes from the root down, then the old proof below the first untouched subtree
+        let mut above = Vec::new();
+        let mut below: Vec<ProofNode> = Vec::new();
+        while at.len < 256 && prefix_match_be(&target, &at.path, at.len) {
+            match (child(&view, at.path, at.len, 0).ok()?, child(&view, at.path, at.len, 1).ok()?) {
+                (Some(left), Some(right)) => {
+                    let dir = get_bit_be(&target, at.len);
+                    let (next, sibling) = if dir == 0 { (left, right) } else { (right, left) };
+                    let sibling_hash = H::bind(sibling.0.len, &sibling.0.path, &sibling.1);
+                    above.push(ProofNode { hash: sibling_hash, direction: 1 - dir, len: at.len });
+                    (at, at_hash) = next;
+                }
+                (None, None) => {
+                    below = old.nodes.iter().filter(|node| node.len >= at.len).cloned().collect();
+                    (at, at_hash) = (NodeKey { path: old.path, len: old.len }, old.hash);
+                    break;
+                }
+                _ => return None,
+            }
+        }
+        above.reverse();
+        below.extend(above);
+
+        let unchanged = at.path == old.path && at.len == old.len && at_hash == old.hash;
+        let value = old.value.clone().filter(|_| unchanged);
+        let proof = Proof { root: changes.root, nodes: below, path: at.path, hash: at_hash, len: at.len, value };
+        (proof.verify_integrity::<H>() && verify_structure(&proof)).then_some(proof)
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::Sha256Hasher;
+    use crate::hubt_mem::{Hubt, ValueHubt};
+    use crate::proof::VerifyStatus;
+    use crate::test_util::key;
+    use crate::tree::Op;
+
+    #[test]
+    fn test_proofs_follow_batches() {
+        let mut hubt = Hubt::new();
+        hubt.batch_update((0..2000).step_by(2).map(|i| Op::Insert(key(i), b"v".to_vec())).collect()).unwrap();
+
+        // A wallet holding proofs for present and absent keys
+        let watched: Vec<u32> = (0..60).map(|i| i * 37 % 2000).collect();
+        let mut proofs: Vec<Proof> = watched.iter().map(|&i| hubt.prove(key(i)).unwrap()).collect();
+        let batches: Vec<Vec<Op>> = vec![
+            (0..2000).step_by(7).map(|i| Op::Insert(key(i), b"w".to_vec())).collect(),
+            (0..2000).step_by(5).map(|i| Op::Delete(key(i))).collect(),
+            vec![Op::Delete(b"missing".to_vec())],
+            (0..2000).map(|i| Op::Insert(key(i), b"x".to_vec())).collect(),
+            (1..2000).map(|i| Op::Delete(key(i))).collect(),
+            vec![Op::Delete(key(0))],
+            vec![Op::Insert(key(74), b"y".to_vec())],
+        ];
+        for ops in batches {
+            let result = hubt.batch_update(ops).unwrap();
+            let changes = hubt.change_set(&result.changed).unwrap();
+            assert_eq!(changes.root, result.new_root);
+            for (proof, &i) in proofs.iter_mut().zip(&watched) {
+                *proof = changes.update_proof::<Sha256Hasher>(proof, KeyMode::Hashed, &key(i)).unwrap();
+                let fresh = hubt.prove(key(i)).unwrap();
+                if fresh.len == 256 && fresh.path == KeyMode::Hashed.path::<Sha256Hasher>(&key(i)) {
+                    assert_eq!(*proof, fresh);
+                } else {
+                    assert_eq!(Hubt::verify(proof, key(i), vec![]), VerifyStatus::NonExistence);
+                }
+            }
+        }
+
+        // A change set from another batch does not fit
+        let result = hubt.batch_update(vec![Op::Insert(key(5), b"v".to_vec())]).unwrap();
+        let changes = hubt.change_set(&result.changed).unwrap();
+        let mut stale = changes.clone();
+        stale.nodes.retain(|(node, _)| node.len == 256);
+        assert!(stale.update_proof::<Sha256Hasher>(&proofs[2], KeyMode::Hashed, &key(watched[2])).is_none());
+    }
+
+    #[test]
+    fn test_updated_proof_keeps_value() {
+        let mut hubt = ValueHubt::new();
+        hubt.batch_update((0..100).map(|i| Op::Insert(key(i), b"v".to_vec())).collect()).unwrap();
+        let proof = hubt.prove(key(1)).unwrap();
+
+        let result = hubt.batch_update(vec![Op::Insert(key(2), b"w".to_vec())]).unwrap();
+        let updated = hubt.change_set(&result.changed).unwrap().update_proof::<Sha256Hasher>(&proof, KeyMode::Hashed, &key(1)).unwrap();
+        assert_eq!(updated, hubt.prove(key(1)).unwrap());
+
+        let result = hubt.batch_update(vec![Op::Insert(key(1), b"w".to_vec())]).unwrap();
+        let updated = hubt.change_set(&result.changed).unwrap().update_proof::<Sha256Hasher>(&updated, KeyMode::Hashed, &key(1)).unwrap();
+        assert_eq!(updated.value, None);
+        assert_eq!(Hubt::verify(&updated, key(1), b"w".to_vec()), VerifyStatus::Included);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::bits::Path;
+use crate::bulk::internal_nodes;
+use crate::error::Result;
+use crate::hasher::{Hash, TreeHasher, ZERO_HASH};
+use crate::store::{HubtRead, HubtStore, NodeKey};
+use crate::tree::{bound, child, Tree};
+
+/// Something `Tree::check` found wrong with a stored node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Problem {
+    /// Two adjacent leaves branch here but there is no node.
+    MissingNode(NodeKey),
+    /// The node's hash is not the hash of its two children.
+    BadHash(NodeKey),
+    /// An internal node with a missing child, it should have been pruned.
+    MissingChild(NodeKey),
+    /// A `ZERO_HASH` split point that was never rehashed.
+    Placeholder(NodeKey),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckReport {
+    /// Root as stored.
+    pub root: Hash,
+    /// Root recomputed from the leaves alone.
+    pub expected_root: Hash,
+    /// Problems in node order.
+    pub problems: Vec<Problem>,
+}
+
+impl CheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty() && self.root == self.expected_root
+    }
+}
+
+impl<S: HubtRead, H: TreeHasher> Tree<S, H> {
+    /// Verifies every internal node against its children, the branch points of the leaves and the root.
+    pub fn check(&self) -> Result<CheckReport> {
+        let mut problems = Vec::new();
+        let mut cursor = Some(NodeKey { path: [0u8; 32], len: 0 });
+        while let Some((key, hash)) = match cursor {
+            Some(cursor) => self.store.seek_next(&cursor)?,
+            None => None,
+        } {
+            cursor = Some(NodeKey { path: key.path, len: key.len + 1 });
+            if key.len == 256 {
+                continue;
+            }
+            let l_child = child(&self.store, key.path, key.len, 0)?;
+            let r_child = child(&self.store, key.path, key.len, 1)?;
+            let problem = match (l_child, r_child) {
+                _ if hash == ZERO_HASH => Some(Problem::Placeholder(key)),
+                (Some((l_key, l_hash)), Some((r_key, r_hash))) => {
+                    let (l_bound, r_bound) = (bound::<S, H>(&self.store, &l_key, l_hash), bound::<S, H>(&self.store, &r_key, r_hash));
+                    (H::node(&l_bound, &r_bound) != hash).then_some(Problem::BadHash(key))
+                }
+                _ => Some(Problem::MissingChild(key)),
+            };
+            problems.extend(problem);
+        }
+
+        // Every branch point of the leaves needs a node
+        let leaves = self.leaves().collect::<Result<Vec<_>>>()?;
+        let expected_root = internal_nodes::<H>(leaves.iter().map(|leaf| (leaf.path, leaf.hash)), |key, _| {
+            if self.store.get(&key)?.is_none() {
+                problems.push(Problem::MissingNode(key));
+            }
+            Ok(())
+        })?;
+        problems.sort_by_key(|problem| match problem {
+            Problem::MissingNode(key) | Problem::BadHash(key) | Problem::MissingChild(key) | Problem::Placeholder(key) => *key,
+        });
+        Ok(CheckReport { root: self.root()?, expected_root, problems })
+    }
+}
+
+impl<S: HubtStore, H: TreeHasher> Tree<S, H> {
+    /// Runs `check` and, if anything is wrong, rebuilds every internal node from the leaves.
+    /// Returns what `check` found before the repair.
+    pub fn repair(&mut self) -> Result<CheckReport> {
+        let report = self.check()?;
+        if report.is_ok() {
+            return Ok(report);
+        }
+        self.store.start_batch()?;
+        let mut cursor = NodeKey { path: [0u8; 32], len: 0 };
+        while let Some((key, _)) = self.store.seek_next(&cursor)? {
+            if key.len < 256 {
+                self.store.delete(&key)?;
+            }
+            cursor = NodeKey { path: key.path, len: key.len + 1 };
+        }
+        let leaves: Vec<(Path, Hash)> = self.leaves().map(|leaf| leaf.map(|leaf| (leaf.path, leaf.hash))).collect::<Result<_>>()?;
+        let store = &mut self.store;
+        internal_nodes::<H>(leaves, |key, hash| store.put(key, hash))?;
+        Ok(report)
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::lcp_be;
+    use crate::hubt_mem::Hubt;
+    use crate::tree::Op;
+    use std::collections::BTreeSet;
+
+    fn filled() -> Hubt {
+        let mut hubt = Hubt::new();
+        hubt.batch_update((0..500u32).map(|i| Op::Insert(i.to_be_bytes().to_vec(), b"v".to_vec())).collect()).unwrap();
+        hubt
+    }
+
+    #[test]
+    fn test_check_finds_and_repairs_damage() {
+        let clean = filled();
+        let report = clean.check().unwrap();
+        assert!(report.is_ok());
+        assert_eq!(report.root, clean.root().unwrap());
+        assert!(Hubt::new().check().unwrap().is_ok());
+
+        let mut hubt = filled();
+        let internal: Vec<NodeKey> = hubt.store.keys().filter(|k| k.len < 256 && k.len > 0).copied().collect();
+        let leaves: Vec<NodeKey> = hubt.store.keys().filter(|k| k.len == 256).copied().collect();
+
+        // A dropped branch node, a flipped node hash, a flipped leaf and a split point left unhashed
+        hubt.store.remove(&internal[10]);
+        hubt.store.get_mut(&internal[50]).unwrap()[0] ^= 1;
+        hubt.store.get_mut(&leaves[200]).unwrap()[0] ^= 1;
+        let (path, len) = lcp_be(&leaves[300].path, &leaves[301].path);
+        let mut dirty = BTreeSet::new();
+        hubt.store.remove(&NodeKey { path, len });
+        hubt.ensure_split_points(leaves[300].path, &mut dirty).unwrap();
+        let placeholder = NodeKey { path, len };
+
+        let report = hubt.check().unwrap();
+        assert!(!report.is_ok());
+        assert_ne!(report.root, report.expected_root);
+        assert!(report.problems.contains(&Problem::MissingNode(internal[10])));
+        assert!(report.problems.contains(&Problem::BadHash(internal[50])));
+        assert!(report.problems.contains(&Problem::Placeholder(placeholder)));
+        let parent = hubt.ancestors(leaves[200].path, 256).unwrap()[0];
+        assert!(report.problems.contains(&Problem::BadHash(parent)));
+
+        // Repair keeps the leaves, the flipped one included, and rebuilds the rest
+        assert_eq!(hubt.repair().unwrap(), report);
+        assert!(hubt.check().unwrap().is_ok());
+        assert_eq!(hubt.root().unwrap(), report.expected_root);
+
+        // An internal node left behind by a missing prune
+        let mut hubt = filled();
+        let stale = NodeKey { path: leaves[0].path, len: 255 };
+        hubt.store.insert(stale, [7u8; 32]);
+        assert!(hubt.check().unwrap().problems.contains(&Problem::MissingChild(stale)));
+        hubt.repair().unwrap();
+        assert!(hubt.store == clean.store);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::bits::{get_bit_be, prefix_match_be, Path};
+use crate::error::Result;
+use crate::hasher::{Hash, TreeHasher};
+use crate::store::{HubtRead, NodeKey};
+use crate::tree::{child, Tree};
+
+/// Leaf paths that differ between two trees, each list in path order, see `Tree::diff`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diff {
+    pub added: Vec<Path>,
+    pub removed: Vec<Path>,
+    pub modified: Vec<Path>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+type Node = Option<(NodeKey, Hash)>;
+
+impl<S: HubtRead, H: TreeHasher> Tree<S, H> {
+    /// Leaves added, removed and modified going from this tree to `new`.
+    ///
+    /// Both trees are walked top-down from the root, equal subtrees are skipped by their hash,
+    /// so the cost follows the size of the change rather than the size of the trees.
+    pub fn diff<T: HubtRead>(&self, new: &Tree<T, H>) -> Result<Diff> {
+        let top = NodeKey { path: [0u8; 32], len: 0 };
+        let mut diff = Diff::default();
+        self.diff_nodes(new, self.store.seek_next(&top)?, new.store.seek_next(&top)?, &mut diff)?;
+        diff.added.sort_unstable();
+        diff.removed.sort_unstable();
+        diff.modified.sort_unstable();
+        Ok(diff)
+    }
+
+    fn diff_nodes<T: HubtRead>(&self, new: &Tree<T, H>, old_node: Node, new_node: Node, diff: &mut Diff) -> Result<()> {
+        match (old_node, new_node) {
+            (None, None) => Ok(()),
+            (Some((old, _)), None) => collect(self, old, &mut diff.removed),
+            (None, Some((new_key, _))) => collect(new, new_key, &mut diff.added),
+            (Some((old, old_hash)), Some((new_key, new_hash))) => {
+                if old == new_key {
+                    if old_hash == new_hash {
+                        return Ok(());
+                    }
+                    if old.len == 256 {
+                        diff.modified.push(old.path);
+                        return Ok(());
+                    }
+                    for dir in 0..2 {
+                        let (o, n) = (child(&self.store, old.path, old.len, dir)?, child(&new.store, old.path, old.len, dir)?);
+                        self.diff_nodes(new, o, n, diff)?;
+                    }
+                    Ok(())
+                } else if old.len < new_key.len && prefix_match_be(&new_key.path, &old.path, old.len) {
+                    // The new subtree sits on one side of the old branch, the other side is gone
+                    let dir = get_bit_be(&new_key.path, old.len);
+                    self.diff_nodes(new, child(&self.store, old.path, old.len, dir)?, new_node, diff)?;
+                    match child(&self.store, old.path, old.len, 1 - dir)? {
+                        Some((gone, _)) => collect(self, gone, &mut diff.removed),
+                        None => Ok(()),
+                    }
+                } else if new_key.len < old.len && prefix_match_be(&old.path, &new_key.path, new_key.len) {
+                    let dir = get_bit_be(&old.path, new_key.len);
+                    self.diff_nodes(new, old_node, child(&new.store, new_key.path, new_key.len, dir)?, diff)?;
+                    match child(&new.store, new_key.path, new_key.len, 1 - dir)? {
+                        Some((fresh, _)) => collect(new, fresh, &mut diff.added),
+                        None => Ok(()),
+                    }
+                } else {
+                    // Disjoint subtrees
+                    collect(self, old, &mut diff.removed)?;
+                    collect(new, new_key, &mut diff.added)
+                }
+            }
+        }
+    }
+}
+
+/// Every leaf under `node`.
+fn collect<S: HubtRead, H: TreeHasher>(tree: &Tree<S, H>, node: NodeKey, out: &mut Vec<Path>) -> Result<()> {
+    for leaf in tree.leaves_under(node.path, node.len) {
+        out.push(leaf?.path);
+    }
+    Ok(())
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hubt_mem::{Hubt, VersionedHubt};
+    use crate::test_util::key;
+    use crate::tree::Op;
+    use std::collections::BTreeMap;
+
+    /// The diff by comparing every leaf.
+    fn full_diff(old: &Hubt, new: &Hubt) -> Diff {
+        let leaves = |t: &Hubt| -> BTreeMap<Path, Hash> { t.leaves().map(|l| l.map(|l| (l.path, l.hash))).collect::<Result<_>>().unwrap() };
+        let (old, new) = (leaves(old), leaves(new));
+        Diff {
+            added: new.keys().filter(|p| !old.contains_key(*p)).copied().collect(),
+            removed: old.keys().filter(|p| !new.contains_key(*p)).copied().collect(),
+            modified: new.iter().filter(|(p, h)| old.get(*p).is_some_and(|o| o != *h)).map(|(p, _)| *p).collect(),
+        }
+    }
+
+    #[test]
+    fn test_diff_matches_full_comparison() {
+        let mut old = Hubt::new();
+        old.batch_update((0..2000).map(|i| Op::Insert(key(i), b"a".to_vec())).collect()).unwrap();
+        let mut new = Hubt::new();
+        new.batch_update((0..2000).map(|i| Op::Insert(key(i), b"a".to_vec())).collect()).unwrap();
+        assert!(old.diff(&new).unwrap().is_empty());
+
+        let mut ops: Vec<Op> = (0..2000).step_by(97).map(|i| Op::Delete(key(i))).collect();
+        ops.extend((1..2000).step_by(101).map(|i| Op::Insert(key(i), b"b".to_vec())));
+        ops.extend((5000..5030).map(|i| Op::Insert(key(i), b"a".to_vec())));
+        new.batch_update(ops).unwrap();
+
+        let diff = old.diff(&new).unwrap();
+        assert_eq!(diff, full_diff(&old, &new));
+        assert_eq!((diff.added.len(), diff.removed.len(), diff.modified.len()), (30, 21, 20));
+        assert_eq!(new.diff(&old).unwrap(), Diff { added: diff.removed.clone(), removed: diff.added.clone(), modified: diff.modified.clone() });
+
+        // Empty and single leaf trees
+        let mut single = Hubt::new();
+        single.batch_update(vec![Op::Insert(key(7), b"a".to_vec())]).unwrap();
+        for (a, b) in [(&Hubt::new(), &new), (&new, &Hubt::new()), (&single, &new), (&new, &single), (&single, &Hubt::new())] {
+            assert_eq!(a.diff(b).unwrap(), full_diff(a, b));
+        }
+    }
+
+    #[test]
+    fn test_diff_between_versions() {
+        let mut hubt = VersionedHubt::new();
+        hubt.commit((0..300).map(|i| Op::Insert(key(i), b"a".to_vec())).collect()).unwrap();
+        hubt.commit(vec![Op::Insert(key(1), b"b".to_vec()), Op::Delete(key(2)), Op::Insert(key(900), b"a".to_vec())]).unwrap();
+
+        let diff = hubt.diff_at(1, 2).unwrap();
+        let path = |i: u32| crate::hasher::Sha256Hasher::hash(&key(i));
+        assert_eq!(diff, Diff { added: vec![path(900)], removed: vec![path(2)], modified: vec![path(1)] });
+        assert!(hubt.diff_at(2, 2).unwrap().is_empty());
+    }
+}
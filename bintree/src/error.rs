@@ -0,0 +1,75 @@
+use rocksdb::ErrorKind;
+use std::fmt::{self, Display};
+
+pub type Result<T> = std::result::Result<T, HubtError>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HubtError {
+    /// The backing store failed a read or a write.
+    Storage(String),
+    /// The store hit a lock conflict or timeout, the same transaction can be retried.
+    Busy(String),
+    /// A stored node hash is not 32 bytes.
+    CorruptNode { key: Vec<u8>, len: usize },
+    /// A stored node key is not `path (32) || len (2)` with `len <= 256`.
+    BadKeyLength(Vec<u8>),
+    /// A stored leaf value is not `key len (4) || key || value`.
+    CorruptValue(Vec<u8>),
+    /// The version has not been committed yet.
+    UnknownVersion(u64),
+    /// The version was removed by pruning.
+    Pruned(u64),
+    /// Bulk loaded leaves are not strictly ascending by path, at this path.
+    UnsortedLeaves(Vec<u8>),
+    /// Bulk loading and snapshot import need an empty tree.
+    NotEmpty,
+    /// A snapshot chunk does not verify against the root or does not follow the previous one.
+    InvalidChunk,
+    /// The checkpoint is ahead of the undo journal or was released.
+    UnknownCheckpoint(u64),
+    /// The condition of the batch op at `index` does not hold, the batch was not applied.
+    ConditionFailed { index: usize, key: Vec<u8> },
+    /// A witness does not verify against its root.
+    InvalidWitness,
+    /// The leaf at this path is under a subtree the witness does not reveal.
+    NotInWitness(Vec<u8>),
+    /// Encoded proof bytes are malformed or not canonical, see `wire::Wire`.
+    InvalidEncoding(String),
+}
+
+impl Display for HubtError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HubtError::Storage(msg) => write!(f, "storage error: {}", msg),
+            HubtError::Busy(msg) => write!(f, "storage busy: {}", msg),
+            HubtError::CorruptNode { key, len } => write!(f, "corrupt node {}: {} byte hash", hex(key), len),
+            HubtError::BadKeyLength(key) => write!(f, "bad node key {} ({} bytes)", hex(key), key.len()),
+            HubtError::CorruptValue(path) => write!(f, "corrupt value for leaf {}", hex(path)),
+            HubtError::UnknownVersion(v) => write!(f, "unknown version {}", v),
+            HubtError::Pruned(v) => write!(f, "version {} is pruned", v),
+            HubtError::UnsortedLeaves(path) => write!(f, "leaf {} is out of order", hex(path)),
+            HubtError::NotEmpty => write!(f, "bulk load or snapshot import into a non empty tree"),
+            HubtError::InvalidChunk => write!(f, "snapshot chunk does not verify"),
+            HubtError::UnknownCheckpoint(c) => write!(f, "unknown checkpoint {}", c),
+            HubtError::ConditionFailed { index, key } => write!(f, "condition of op {} on key {} failed", index, hex(key)),
+            HubtError::InvalidWitness => write!(f, "witness does not verify"),
+            HubtError::NotInWitness(path) => write!(f, "leaf {} is not covered by the witness", hex(path)),
+            HubtError::InvalidEncoding(msg) => write!(f, "invalid proof encoding: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for HubtError {}
+
+impl From<rocksdb::Error> for HubtError {
+    fn from(e: rocksdb::Error) -> Self {
+        match e.kind() {
+            ErrorKind::Busy | ErrorKind::TimedOut | ErrorKind::TryAgain => HubtError::Busy(e.into_string()),
+            _ => HubtError::Storage(e.into_string()),
+        }
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
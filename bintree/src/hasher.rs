@@ -0,0 +1,188 @@
+use ark_bn254::Fr;
+use ark_ff::{BigInteger, PrimeField};
+use light_poseidon::{Poseidon, PoseidonHasher as _};
+use sha3::{Digest, Keccak256};
+use std::cell::RefCell;
+
+pub use hubt_verifier::hasher::{Hardened, Hash, KeyMode, Sha256Hasher, TreeHasher, BIND_TAG, LEAF_TAG, NODE_TAG};
+pub(crate) use hubt_verifier::hasher::ZERO_HASH;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Blake3Hasher;
+
+impl TreeHasher for Blake3Hasher {
+    #[inline]
+    fn hash(data: &[u8]) -> Hash {
+        blake3::hash(data).into()
+    }
+
+    #[inline]
+    fn concat_and_hash(a: &[u8], b: &[u8]) -> Hash {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(a);
+        hasher.update(b);
+        hasher.finalize().into()
+    }
+
+    #[inline]
+    fn hash_parts(parts: &[&[u8]]) -> Hash {
+        let mut hasher = blake3::Hasher::new();
+        parts.iter().for_each(|part| { hasher.update(part); });
+        hasher.finalize().into()
+    }
+}
+
+/// Keccak-256 as used by the EVM (original padding, not SHA3-256).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Keccak256Hasher;
+
+impl TreeHasher for Keccak256Hasher {
+    #[inline]
+    fn hash(data: &[u8]) -> Hash {
+        Keccak256::digest(data).into()
+    }
+
+    #[inline]
+    fn concat_and_hash(a: &[u8], b: &[u8]) -> Hash {
+        let mut hasher = Keccak256::new();
+        hasher.update(a);
+        hasher.update(b);
+        hasher.finalize().into()
+    }
+
+    #[inline]
+    fn hash_parts(parts: &[&[u8]]) -> Hash {
+        let mut hasher = Keccak256::new();
+        parts.iter().for_each(|part| hasher.update(part));
+        hasher.finalize().into()
+    }
+}
+
+/// Circom compatible Poseidon over BN254 (x5, circomlib constants).
+///
+/// Byte inputs are split into 31 byte big endian chunks, each below the field modulus,
+/// and folded as `acc = Poseidon(acc, chunk)` starting from `acc = len(data)`.
+/// Leaves are `Poseidon(LEAF_TAG, hash(k), hash(v))`. Internal nodes split both children
+/// into 16 byte limbs, `Poseidon(NODE_TAG, l_hi, l_lo, r_hi, r_lo)`, so no input is
+/// reduced mod p and the two never share an instance.
+/// Outputs are the big endian encoding of the resulting field element.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PoseidonHasher;
+
+thread_local! {
+    static POSEIDON_2: RefCell<Poseidon<Fr>> = RefCell::new(Poseidon::<Fr>::new_circom(2).unwrap());
+    static POSEIDON_3: RefCell<Poseidon<Fr>> = RefCell::new(Poseidon::<Fr>::new_circom(3).unwrap());
+    static POSEIDON_5: RefCell<Poseidon<Fr>> = RefCell::new(Poseidon::<Fr>::new_circom(5).unwrap());
+}
+
+impl PoseidonHasher {
+    #[inline]
+    fn hash2(a: Fr, b: Fr) -> Fr {
+        POSEIDON_2.with(|p| p.borrow_mut().hash(&[a, b]).unwrap())
+    }
+
+    #[inline]
+    fn limbs(h: &Hash) -> [Fr; 2] {
+        [Fr::from_be_bytes_mod_order(&h[..16]), Fr::from_be_bytes_mod_order(&h[16..])]
+    }
+
+    #[inline]
+    fn to_bytes(f: Fr) -> Hash {
+        let mut out = [0u8; 32];
+        out.copy_from_slice(&f.into_bigint().to_bytes_be());
+        out
+    }
+}
+
+impl TreeHasher for PoseidonHasher {
+    fn hash(data: &[u8]) -> Hash {
+        let acc = data.chunks(31).fold(Fr::from(data.len() as u64), |acc, chunk| {
+            Self::hash2(acc, Fr::from_be_bytes_mod_order(chunk))
+        });
+        Self::to_bytes(acc)
+    }
+
+    fn concat_and_hash(a: &[u8], b: &[u8]) -> Hash {
+        Self::hash(&[a, b].concat())
+    }
+
+    fn leaf(k: &[u8], v: &[u8]) -> Hash {
+        let inputs = [Fr::from(LEAF_TAG as u64), Fr::from_be_bytes_mod_order(&Self::hash(k)), Fr::from_be_bytes_mod_order(&Self::hash(v))];
+        Self::to_bytes(POSEIDON_3.with(|p| p.borrow_mut().hash(&inputs).unwrap()))
+    }
+
+    fn node(left: &Hash, right: &Hash) -> Hash {
+        let ([l_hi, l_lo], [r_hi, r_lo]) = (Self::limbs(left), Self::limbs(right));
+        let inputs = [Fr::from(NODE_TAG as u64), l_hi, l_lo, r_hi, r_lo];
+        Self::to_bytes(POSEIDON_5.with(|p| p.borrow_mut().hash(&inputs).unwrap()))
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(h: &Hash) -> String {
+        h.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_known_answers() {
+        assert_eq!(hex(&Sha256Hasher::hash(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(hex(&Blake3Hasher::hash(b"")), "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+        assert_eq!(hex(&Keccak256Hasher::hash(b"")), "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470");
+
+        // circomlib: poseidon([1, 2])
+        assert_eq!(
+            hex(&PoseidonHasher::to_bytes(PoseidonHasher::hash2(Fr::from(1u64), Fr::from(2u64)))),
+            "115cc0f5e7d690413df64c6b9662e9cf2a3617f2743245519e19607a4417189a"
+        );
+    }
+
+    #[test]
+    fn test_concat_matches_hash_of_concat() {
+        let (a, b) = (b"key".as_slice(), b"value".as_slice());
+        assert_eq!(Sha256Hasher::concat_and_hash(a, b), Sha256Hasher::hash(b"keyvalue"));
+        assert_eq!(Blake3Hasher::concat_and_hash(a, b), Blake3Hasher::hash(b"keyvalue"));
+        assert_eq!(Keccak256Hasher::concat_and_hash(a, b), Keccak256Hasher::hash(b"keyvalue"));
+        assert_eq!(PoseidonHasher::concat_and_hash(a, b), PoseidonHasher::hash(b"keyvalue"));
+        assert_ne!(PoseidonHasher::hash(b""), PoseidonHasher::hash(&[0u8]));
+    }
+
+    #[test]
+    fn test_poseidon_collisions() {
+        // x and x + p were the same field element, 32 byte leaves had the shape of a node
+        let mut one = [0u8; 32];
+        one[31] = 1;
+        let mut one_plus_p = Fr::MODULUS;
+        one_plus_p.add_with_carry(&Fr::from(1u64).into_bigint());
+        let one_plus_p: Hash = one_plus_p.to_bytes_be().try_into().unwrap();
+        let h = PoseidonHasher::hash(b"h");
+        assert_ne!(PoseidonHasher::node(&one, &h), PoseidonHasher::node(&one_plus_p, &h));
+        assert_ne!(PoseidonHasher::node(&h, &one), PoseidonHasher::node(&h, &one_plus_p));
+        assert_ne!(PoseidonHasher::concat_and_hash(&one, &h), PoseidonHasher::concat_and_hash(&one_plus_p, &h));
+        assert_ne!(PoseidonHasher::leaf(&one, &h), PoseidonHasher::leaf(&one_plus_p, &h));
+        assert_ne!(PoseidonHasher::leaf(&one, &h), PoseidonHasher::node(&one, &h));
+        assert_ne!(PoseidonHasher::leaf(b"ab", b"c"), PoseidonHasher::leaf(b"a", b"bc"));
+    }
+
+    #[test]
+    fn test_hardened_domain_separation() {
+        type Hard = Hardened<Sha256Hasher>;
+        // Plain leaves collide across k/v splits and with internal nodes
+        assert_eq!(Sha256Hasher::leaf(b"ab", b"c"), Sha256Hasher::leaf(b"a", b"bc"));
+        assert_ne!(Hard::leaf(b"ab", b"c"), Hard::leaf(b"a", b"bc"));
+        let (l, r) = (Sha256Hasher::hash(b"l"), Sha256Hasher::hash(b"r"));
+        assert_eq!(Sha256Hasher::node(&l, &r), Sha256Hasher::leaf(&l, &r));
+        assert_ne!(Hard::node(&l, &r), Hard::leaf(&l, &r));
+        assert_ne!(Hard::node(&l, &r), Hard::leaf(&[&l[..], &r[..1]].concat(), &r[1..]));
+
+        assert_eq!(Hard::hash(b"abc"), Sha256Hasher::hash(b"abc"));
+        assert_eq!(Hard::leaf(b"k", b"v"), Sha256Hasher::hash(b"\x00\x00\x00\x00\x01k\x00\x00\x00\x01v"));
+        assert_eq!(Hard::node(&l, &r), Sha256Hasher::hash(&[&[1u8][..], &l, &r].concat()));
+    }
+}
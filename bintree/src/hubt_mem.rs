@@ -1,416 +1,128 @@
-use sha2::{Digest, Sha256};
-use std::cmp::{min, Ordering};
-use std::collections::{BTreeMap, BTreeSet};
-use std::ops::Bound;
-
-pub type Hash = [u8; 32];
-pub type Path = [u8; 32];
-const ZERO_HASH: Hash = [0u8; 32];
+use crate::hasher::TreeHasher;
+use crate::tree::Tree;
+use crate::values::WithValues;
+use crate::versioned::{MemVersions, VersionedStore};
+use std::collections::BTreeMap;
+
+pub use crate::bits::Path;
+pub use crate::changes::ChangeSet;
+pub use crate::check::{CheckReport, Problem};
+pub use crate::diff::Diff;
+pub use crate::error::HubtError;
+pub use crate::hasher::{Hardened, Hash, KeyMode, Sha256Hasher};
+pub use crate::multiproof::{FoundNode, MultiProof, MultiStep};
+pub use crate::proof::{Proof, ProofNode, VerifyStatus};
+pub use crate::scan::Leaf;
+pub use crate::snapshot::{ImportState, SnapshotChunk};
+pub use crate::store::NodeKey;
+pub use crate::subtree::SubtreeProof;
+pub use crate::tree::{BatchResult, Op, OpOutcome};
+pub use crate::undo::UndoStore;
+pub use crate::versioned::VersionedKey;
+pub use crate::wire::Wire;
+pub use crate::witness::WitnessStore;
 
 // ============================================================================
-// STRUCTS
+// HUBT IMPLEMENTATION
 // ============================================================================
 
-#[derive(Debug, Clone, PartialEq, Eq, Copy)]
-pub struct NodeKey {
-    pub path: Path,
-    pub len: u16,
-}
+/// In-memory HUBT: the tree engine over a `BTreeMap`.
+pub type Hubt<H = Sha256Hasher> = Tree<BTreeMap<NodeKey, Hash>, H>;
 
-impl PartialOrd for NodeKey {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+impl<H: TreeHasher> Default for Hubt<H> {
+    fn default() -> Self {
+        Self::with_mode(KeyMode::Hashed)
     }
 }
 
-impl Ord for NodeKey {
-    fn cmp(&self, other: &Self) -> Ordering {
-        // Big Endian sort: Path first, then Length
-        match self.path.cmp(&other.path) {
-            Ordering::Equal => self.len.cmp(&other.len),
-            other => other,
-        }
+impl Hubt {
+    pub fn new() -> Self {
+        Self::with_mode(KeyMode::Hashed)
     }
-}
-
-#[derive(Debug)]
-pub enum Op {
-    Insert(Vec<u8>, Vec<u8>),
-    Delete(Vec<u8>),
-}
-
-/// A simplified proof node without length.
-#[derive(Debug, Clone)]
-pub struct ProofNode {
-    pub hash: Hash,
-    pub direction: u8
-}
-
-/// The Universal Proof Struct.
-///
-/// - If `path` == sha256(key) and `hash` == sha256(key, value): It's an Inclusion Proof.
-/// - If `path` == sha256(key) and `hash` != sha256(key, value): It's a Mismatch Proof.
-/// - If `path` != sha256(key): It's a Non-Existence Proof (pointing to the closest ancestor).
-#[derive(Debug, Clone)]
-pub struct Proof {
-    pub root: Hash,
-    pub nodes: Vec<ProofNode>,
-    pub path: Path, // The path of the leaf node actually found in the tree
-    pub hash: Hash, // The hash of the leaf node actually found in the tree
-}
 
-#[derive(Debug, PartialEq)]
-pub enum VerifyStatus {
-    Included,       // Key exists and Value matches
-    Mismatch,       // Key exists but Value is different
-    NonExistence,   // Key does not exist
-    Invalid,        // The proof itself is mathematically invalid (bad root/chain)
-}
+    /// Verifies the proof and determines the relationship between the Key, Value, and the Tree.
+    pub fn verify(proof: &Proof, k: Vec<u8>, v: Vec<u8>) -> VerifyStatus {
+        Self::verify_with_mode(KeyMode::Hashed, proof, k, v)
+    }
 
-// ============================================================================
-// BIT HELPERS (Optimized & Inlined)
-// ============================================================================
-#[inline(always)]
-fn get_bit_be(data: &[u8], index: u16) -> u8 {
-    if index >= 256 { return 0; }
-    let byte_idx = (index >> 3) as usize;
-    let bit_offset = 7 - (index & 7);
-    (data[byte_idx] >> bit_offset) & 1
+    /// Verifies a `prove_many` multiproof, one status per `(k, v)`.
+    pub fn verify_many(proof: &MultiProof, kvs: &[(Vec<u8>, Vec<u8>)]) -> Vec<VerifyStatus> {
+        Self::verify_many_with_mode(KeyMode::Hashed, proof, kvs)
+    }
 }
 
-#[inline(always)]
-fn set_bit_be(data: &mut [u8], index: u16, val: u8) {
-    if index >= 256 { return; }
-    let byte_idx = (index >> 3) as usize;
-    let bit_offset = 7 - (index & 7);
-    if val == 1 {
-        data[byte_idx] |= 1 << bit_offset;
-    } else {
-        data[byte_idx] &= !(1 << bit_offset);
+impl<H: TreeHasher> Hubt<H> {
+    pub fn with_mode(mode: KeyMode) -> Self {
+        Self::with_store(BTreeMap::new(), mode)
     }
 }
 
-#[inline]
-fn mask_after_be(data: &mut [u8], len: u16) {
-    if len >= 256 { return; }
-    let byte_idx = (len >> 3) as usize;
-    let start_clean_bit = len;
+/// In-memory HUBT that also keeps the key/value bytes of each leaf, see `get`.
+pub type ValueHubt<H = Sha256Hasher> = Tree<WithValues<BTreeMap<NodeKey, Hash>, BTreeMap<Path, (Vec<u8>, Vec<u8>)>>, H>;
 
-    for i in start_clean_bit..((byte_idx as u16 + 1) << 3) {
-        let off = 7 - (i & 7);
-        data[byte_idx] &= !(1 << off);
-    }
-    if byte_idx + 1 < 32 {
-        data[(byte_idx + 1)..].fill(0);
+impl<H: TreeHasher> Default for ValueHubt<H> {
+    fn default() -> Self {
+        Self::with_mode(KeyMode::Hashed)
     }
 }
 
-fn lcp_be(p1: &Path, p2: &Path) -> (Path, u16) {
-    let mut len = 0;
-    let mut byte_idx = 0;
-    while byte_idx < 32 && p1[byte_idx] == p2[byte_idx] {
-        len += 8;
-        byte_idx += 1;
-    }
-    if byte_idx < 32 {
-        for i in 0..8 {
-            let idx = (byte_idx * 8) + i;
-            if get_bit_be(p1, idx as u16) == get_bit_be(p2, idx as u16) {
-                len += 1;
-            } else {
-                break;
-            }
-        }
+impl ValueHubt {
+    pub fn new() -> Self {
+        Self::with_mode(KeyMode::Hashed)
     }
-    let mut prefix = *p1;
-    mask_after_be(&mut prefix, len);
-    (prefix, len)
 }
 
-#[inline]
-fn prefix_match_be(target: &Path, path: &Path, len: u16) -> bool {
-    let full_bytes = (len >> 3) as usize;
-    if target[..full_bytes] != path[..full_bytes] {
-        return false;
-    }
-    let rem = len & 7;
-    if rem > 0 {
-        let mask = 0xFF << (8 - rem);
-        if (target[full_bytes] & mask) != (path[full_bytes] & mask) {
-            return false;
-        }
+impl<H: TreeHasher> ValueHubt<H> {
+    pub fn with_mode(mode: KeyMode) -> Self {
+        Self::with_store(WithValues { nodes: BTreeMap::new(), values: BTreeMap::new() }, mode)
     }
-    true
-}
-
-#[inline]
-fn sha256(data: &[u8]) -> Hash {
-    let mut hasher = Sha256::new();
-    hasher.update(data);
-    hasher.finalize().into()
 }
 
-#[inline]
-fn concat_and_hash(a: &[u8], b: &[u8]) -> Hash {
-    let mut hasher = Sha256::new();
-    hasher.update(a);
-    hasher.update(b);
-    hasher.finalize().into()
-}
-
-// ============================================================================
-// HUBT IMPLEMENTATION
-// ============================================================================
+/// In-memory HUBT keeping every committed version, see `commit`, `root_at` and `prove_at`.
+pub type VersionedHubt<H = Sha256Hasher> = Tree<VersionedStore<MemVersions>, H>;
 
-pub struct Hubt {
-    pub store: BTreeMap<NodeKey, Hash>,
+impl<H: TreeHasher> Default for VersionedHubt<H> {
+    fn default() -> Self {
+        Self::with_mode(KeyMode::Hashed)
+    }
 }
 
-impl Hubt {
+impl VersionedHubt {
     pub fn new() -> Self {
-        Hubt { store: BTreeMap::new() }
-    }
-
-    pub fn root(&self) -> Hash {
-        self.store.iter().next().map(|(_, h)| *h).unwrap_or(ZERO_HASH)
+        Self::with_mode(KeyMode::Hashed)
     }
+}
 
-    // --- BATCH UPDATE ---
-    pub fn batch_update(&mut self, ops: Vec<Op>) {
-        let mut prepared: Vec<(bool, Path, Hash)> = ops.into_iter().map(|op| {
-            match op {
-                Op::Insert(k, v) => (true, sha256(&k), concat_and_hash(&k, &v)),
-                Op::Delete(k) => (false, sha256(&k), ZERO_HASH)
-            }
-        }).collect();
-
-        prepared.sort_unstable_by(|a, b| a.1.cmp(&b.1));
-
-        for (is_ins, p, _) in &prepared {
-            if !*is_ins {
-                self.store.remove(&NodeKey { path: *p, len: 256 });
-            }
-        }
-
-        let mut inserts = Vec::with_capacity(prepared.len());
-        for (is_ins, p, l) in &prepared {
-            if *is_ins {
-                self.store.insert(NodeKey { path: *p, len: 256 }, *l);
-                inserts.push((*p, *l));
-            }
-        }
-
-        for (p, l) in &inserts {
-            self.ensure_split_points(*p, *l);
-        }
-
-        let mut dirty_set = BTreeSet::new();
-        for (_, p, _) in &prepared {
-            self.collect_dirty_ancestors(*p, &mut dirty_set);
-        }
-
-        self.rehash_and_prune(dirty_set);
-    }
-
-    fn ensure_split_points(&mut self, path: Path, leaf_hash: Hash) {
-        let key = NodeKey { path, len: 256 };
-        if let Some((n_key, n_hash)) = self.store.range(..key).next_back().map(|(k,v)| (*k, *v)) {
-            if n_key.len == 256 { self.check_neighbor(path, leaf_hash, n_key.path, n_hash); }
-        }
-        if let Some((n_key, n_hash)) = self.store.range((Bound::Excluded(key), Bound::Unbounded)).next().map(|(k,v)| (*k, *v)) {
-            if n_key.len == 256 { self.check_neighbor(path, leaf_hash, n_key.path, n_hash); }
-        }
-    }
-
-    fn check_neighbor(&mut self, path: Path, leaf: Hash, n_path: Path, n_leaf: Hash) {
-        let (lcp_path, len) = lcp_be(&path, &n_path);
-        let temp_val = concat_and_hash(&leaf, &n_leaf);
-        self.store.insert(NodeKey { path: lcp_path, len }, temp_val);
-    }
-
-    fn collect_dirty_ancestors(&self, target_path: Path, acc: &mut BTreeSet<NodeKey>) {
-        let mut cursor = NodeKey { path: target_path, len: 256 };
-        loop {
-            match self.store.range(..cursor).next_back() {
-                None => break,
-                Some((k, _)) => {
-                    if prefix_match_be(&target_path, &k.path, k.len) {
-                        acc.insert(*k);
-                        cursor = *k;
-                    } else {
-                        let (lcp_path, lcp_len) = lcp_be(&target_path, &k.path);
-                        let jump_key = NodeKey { path: lcp_path, len: lcp_len + 1 };
-                        cursor = if jump_key < *k { jump_key } else { *k };
-                    }
-                }
-            }
-        }
-    }
-
-    fn rehash_and_prune(&mut self, dirty_nodes: BTreeSet<NodeKey>) {
-        let mut sorted_nodes: Vec<NodeKey> = dirty_nodes.into_iter().collect();
-        sorted_nodes.sort_unstable_by(|a, b| b.len.cmp(&a.len));
-
-        for node in sorted_nodes {
-            if node.len == 256 { continue; }
-            let l_hash = self.get_child_hash(node.path, node.len, 0);
-            let r_hash = self.get_child_hash(node.path, node.len, 1);
-
-            if l_hash != ZERO_HASH && r_hash != ZERO_HASH {
-                self.store.insert(node, concat_and_hash(&l_hash, &r_hash));
-            } else {
-                self.store.remove(&node);
-            }
-        }
+impl<H: TreeHasher> VersionedHubt<H> {
+    pub fn with_mode(mode: KeyMode) -> Self {
+        Self::with_store(VersionedStore::new(MemVersions::default()), mode)
     }
+}
 
-    fn get_child_hash(&self, p_path: Path, p_len: u16, dir: u8) -> Hash {
-        let mut target_path = p_path;
-        set_bit_be(&mut target_path, p_len, dir);
-        mask_after_be(&mut target_path, p_len + 1);
-        let target_key = NodeKey { path: target_path, len: p_len + 1 };
-
-        if let Some((f_key, hash)) = self.store.range(target_key..).next() {
-            if prefix_match_be(&f_key.path, &target_path, p_len + 1) {
-                return *hash;
-            }
-        }
-        ZERO_HASH
-    }
-
-    // ========================================================================
-    // UNIFIED PROOF LOGIC
-    // ========================================================================
-
-    /// Generates a single Proof struct that covers Inclusion, Mismatch, or Non-Existence.
-    ///
-    /// The logic detects the state of `k` in the tree:
-    /// 1. Finds the node matching `k` (or the longest matching prefix node).
-    /// 2. Generates the merkle path to that node.
-    /// 3. Returns the proof containing the found node's path and hash.
-    pub fn prove(&self, k: Vec<u8>) -> Proof {
-        let target_path = sha256(&k);
-
-        // Find the node that actually exists (Exact match OR Longest Prefix)
-        let (found_key, found_hash) = match self.find_longest_prefix_node(&target_path) {
-            Some((key, hash)) => (key, hash),
-            None => {
-                // Empty tree case
-                return Proof {
-                    root: ZERO_HASH,
-                    nodes: vec![],
-                    path: ZERO_HASH,
-                    hash: ZERO_HASH
-                };
-            }
-        };
+/// In-memory HUBT journaling every batch, see `revert_last` and `revert_to`.
+pub type UndoHubt<H = Sha256Hasher> = Tree<UndoStore<BTreeMap<NodeKey, Hash>>, H>;
 
-        Proof {
-            root: self.root(),
-            nodes: self.generate_proof_nodes(found_key.path, found_key.len),
-            path: found_key.path,
-            hash: found_hash,
-        }
+impl<H: TreeHasher> Default for UndoHubt<H> {
+    fn default() -> Self {
+        Self::with_mode(KeyMode::Hashed)
     }
+}
 
-    fn generate_proof_nodes(&self, path: Path, len: u16) -> Vec<ProofNode> {
-        let mut ancestors = Vec::new();
-        let mut cursor = NodeKey { path, len: 256 };
-
-        loop {
-            match self.store.range(..cursor).next_back() {
-                None => break,
-                Some((k, _)) => {
-                    if prefix_match_be(&path, &k.path, k.len) {
-                        if k.len < len { ancestors.push(*k); }
-                        cursor = *k;
-                    } else {
-                        let (lcp_p, lcp_l) = lcp_be(&path, &k.path);
-                        let jump = NodeKey{ path: lcp_p, len: lcp_l + 1 };
-                        cursor = if jump < *k { jump } else { *k };
-                    }
-                }
-            }
-        }
-        ancestors.sort_unstable_by(|a, b| b.len.cmp(&a.len));
-
-        let mut nodes = Vec::new();
-        for anc in ancestors {
-            let my_dir = get_bit_be(&path, anc.len);
-            let sibling_dir = 1 - my_dir;
-            nodes.push(ProofNode {
-                hash: self.get_child_hash(anc.path, anc.len, sibling_dir),
-                direction: sibling_dir,
-                // len removed as requested
-            });
-        }
-        nodes
-    }
-
-    fn find_longest_prefix_node(&self, target: &Path) -> Option<(NodeKey, Hash)> {
-        let s_key = NodeKey { path: *target, len: 256 };
-        let prev = self.store.range(..=s_key).next_back();
-        let next = self.store.range((Bound::Excluded(s_key), Bound::Unbounded)).next();
-
-        match (prev, next) {
-            (None, None) => None,
-            (None, Some((k, h))) => Some((*k, *h)),
-            (Some((k, h)), None) => Some((*k, *h)),
-            (Some((pk, ph)), Some((nk, nh))) => {
-                let (_, rp) = lcp_be(target, &pk.path);
-                let (_, rn) = lcp_be(target, &nk.path);
-                if min(rp, pk.len) >= min(rn, nk.len) { Some((*pk, *ph)) } else { Some((*nk, *nh)) }
-            }
-        }
+impl UndoHubt {
+    pub fn new() -> Self {
+        Self::with_mode(KeyMode::Hashed)
     }
+}
 
-    // ========================================================================
-    // UNIFIED VERIFICATION
-    // ========================================================================
-
-    /// Verifies the proof and determines the relationship between the Key, Value, and the Tree.
-    pub fn verify(proof: &Proof, k: Vec<u8>, v: Vec<u8>) -> VerifyStatus {
-        let target_path = sha256(&k);
-        let claimed_leaf_hash = concat_and_hash(&k, &v);
-
-        // 1. Basic Integrity Check: Does the proof path/hash actually hash up to the Root?
-        if !Self::verify_integrity(proof) {
-            return VerifyStatus::Invalid;
-        }
-
-        // 2. Interpret the result
-        if proof.path == target_path {
-            // Path matches exactly.
-            if proof.hash == claimed_leaf_hash {
-                VerifyStatus::Included
-            } else {
-                VerifyStatus::Mismatch
-            }
-        } else {
-            // Path does not match. This is a Non-Existence proof.
-            // Check if proof.path is actually a prefix of target, or a valid divergence point
-            if prefix_match_be(&target_path, &proof.path, 0) { // Should check valid divergence
-                 VerifyStatus::NonExistence
-            } else {
-                 VerifyStatus::Invalid // Should ideally check divergence details here
-            }
-        }
-    }
-
-    fn verify_integrity(proof: &Proof) -> bool {
-        if proof.root == ZERO_HASH { return proof.nodes.is_empty() && proof.hash == ZERO_HASH; }
-
-        let calc = proof.nodes.iter().fold(proof.hash, |acc, node| {
-            if node.direction == 0 {
-                concat_and_hash(&node.hash, &acc)
-            } else {
-                concat_and_hash(&acc, &node.hash)
-            }
-        });
-        calc == proof.root
+impl<H: TreeHasher> UndoHubt<H> {
+    pub fn with_mode(mode: KeyMode) -> Self {
+        Self::with_store(UndoStore::new(BTreeMap::new()), mode)
     }
 }
 
+/// Stateless HUBT over the nodes of a witness, see `from_witness`.
+pub type PartialHubt<H = Sha256Hasher> = Tree<WitnessStore, H>;
+
 // ============================================================================
 // TESTS
 // ============================================================================
@@ -418,6 +130,11 @@ impl Hubt {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bits::{get_bit_be, lcp_be, mask_after_be, set_bit_be};
+    use crate::error::Result;
+    use crate::hasher::{Blake3Hasher, Keccak256Hasher, PoseidonHasher, ZERO_HASH};
+    use crate::store::{HubtRead, HubtStore};
+    use std::collections::BTreeSet;
     use std::time::Instant;
 
     #[test]
@@ -452,14 +169,14 @@ mod tests {
         assert_eq!(lcp_p[0], 0xFF);
         assert_eq!(lcp_p[1], 0x00);
 
-        hubt.check_neighbor(path_a, leaf_val, path_b, leaf_val);
+        hubt.store.insert(NodeKey { path: path_a, len: 256 }, leaf_val);
+        hubt.store.insert(NodeKey { path: path_b, len: 256 }, leaf_val);
+        hubt.ensure_split_points(path_a, &mut BTreeSet::new()).unwrap();
 
         let split_key = NodeKey { path: lcp_p, len: 8 };
         assert!(hubt.store.contains_key(&split_key));
 
-        hubt.store.insert(NodeKey { path: path_a, len: 256 }, leaf_val);
-
-        let found = hubt.get_child_hash(lcp_p, 8, 1);
+        let found = hubt.get_child_hash(lcp_p, 8, 1).unwrap();
         assert_eq!(found, leaf_val);
     }
 
@@ -471,9 +188,9 @@ mod tests {
         hubt.batch_update(vec![
             Op::Insert(b"0".to_vec(), b"0".to_vec()),
             Op::Insert(b"1".to_vec(), b"1".to_vec()),
-        ]);
+        ]).unwrap();
 
-        let root_1 = hubt.root();
+        let root_1 = hubt.root().unwrap();
         let expected_root_1: Hash = [
             238, 97, 151, 15, 183, 44, 176, 246, 70, 241, 213, 115, 32, 121, 65, 80,
             110, 160, 199, 165, 82, 32, 74, 6, 254, 147, 237, 6, 63, 234, 199, 247
@@ -484,9 +201,9 @@ mod tests {
         //println!("\n--- Step 2: Insert 2 ---");
         hubt.batch_update(vec![
             Op::Insert(b"2".to_vec(), b"2".to_vec()),
-        ]);
+        ]).unwrap();
 
-        let root_2 = hubt.root();
+        let root_2 = hubt.root().unwrap();
         let expected_root_2: Hash = [
             217, 231, 118, 162, 16, 188, 97, 238, 129, 73, 252, 176, 156, 109, 43, 97,
             105, 60, 189, 96, 253, 5, 183, 129, 222, 129, 175, 15, 81, 142, 248, 130
@@ -498,14 +215,139 @@ mod tests {
         //println!("\n--- Step 3: Delete 2 ---");
         hubt.batch_update(vec![
             Op::Delete(b"2".to_vec()),
-        ]);
+        ]).unwrap();
 
-        let root_3 = hubt.root();
+        let root_3 = hubt.root().unwrap();
         //println!("Root 3: {:?}", root_3.to_vec());
 
         assert_eq!(root_3, expected_root_1, "Root 3 should revert to Root 1 state");
     }
 
+    #[test]
+    fn test_batch_result() {
+        let mut hubt = Hubt::new();
+        let leaf = |k: &[u8], v: &[u8]| Sha256Hasher::concat_and_hash(k, v);
+        let first = hubt.batch_update(vec![
+            Op::Insert(b"a".to_vec(), b"1".to_vec()),
+            Op::Insert(b"b".to_vec(), b"1".to_vec()),
+            Op::Delete(b"c".to_vec()),
+        ]).unwrap();
+        assert_eq!(first.old_root, ZERO_HASH);
+        assert_eq!(first.new_root, hubt.root().unwrap());
+        assert_eq!(first.outcomes, vec![
+            OpOutcome::Inserted { new: leaf(b"a", b"1") },
+            OpOutcome::Inserted { new: leaf(b"b", b"1") },
+            OpOutcome::DeletedMissing,
+        ]);
+        assert_eq!((first.nodes_written, first.nodes_removed), (1, 0));
+
+        // Outcomes stay in input order, whatever the path order
+        let second = hubt.batch_update(vec![
+            Op::Insert(b"c".to_vec(), b"1".to_vec()),
+            Op::Delete(b"b".to_vec()),
+            Op::Insert(b"a".to_vec(), b"2".to_vec()),
+        ]).unwrap();
+        assert_eq!(second.old_root, first.new_root);
+        assert_eq!(second.outcomes, vec![
+            OpOutcome::Inserted { new: leaf(b"c", b"1") },
+            OpOutcome::Deleted { old: leaf(b"b", b"1") },
+            OpOutcome::Updated { old: leaf(b"a", b"1"), new: leaf(b"a", b"2") },
+        ]);
+        assert_eq!(second.new_root, hubt.root().unwrap());
+
+        // Deleting down to one leaf removes every internal node
+        let n = hubt.store.keys().filter(|key| key.len < 256).count();
+        let last = hubt.batch_update(vec![Op::Delete(b"c".to_vec())]).unwrap();
+        assert_eq!((last.nodes_written, last.nodes_removed), (0, n));
+        assert_eq!(last.new_root, leaf(b"a", b"2"));
+    }
+
+    #[test]
+    fn test_conditional_ops() {
+        let mut hubt = ValueHubt::new();
+        hubt.batch_update(vec![
+            Op::InsertIfAbsent(b"a".to_vec(), b"1".to_vec()),
+            Op::InsertIfAbsent(b"b".to_vec(), b"1".to_vec()),
+        ]).unwrap();
+        let result = hubt.batch_update(vec![
+            Op::CompareAndSwap { k: b"a".to_vec(), expected: b"1".to_vec(), v: b"2".to_vec() },
+            Op::DeleteIfPresent(b"b".to_vec()),
+            Op::InsertIfAbsent(b"c".to_vec(), b"1".to_vec()),
+        ]).unwrap();
+        assert!(matches!(result.outcomes[..], [OpOutcome::Updated { .. }, OpOutcome::Deleted { .. }, OpOutcome::Inserted { .. }]));
+        assert_eq!(hubt.get(b"a").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(hubt.get(b"b").unwrap(), None);
+
+        // One failed condition rejects the whole batch
+        let nodes = hubt.store.nodes.clone();
+        let failing = [
+            (Op::InsertIfAbsent(b"a".to_vec(), b"3".to_vec()), b"a"),
+            (Op::CompareAndSwap { k: b"a".to_vec(), expected: b"1".to_vec(), v: b"3".to_vec() }, b"a"),
+            (Op::CompareAndSwap { k: b"b".to_vec(), expected: b"1".to_vec(), v: b"3".to_vec() }, b"b"),
+            (Op::DeleteIfPresent(b"b".to_vec()), b"b"),
+        ];
+        for (op, key) in failing {
+            let ops = vec![Op::Insert(b"d".to_vec(), b"1".to_vec()), Op::Delete(b"c".to_vec()), op];
+            assert_eq!(hubt.batch_update(ops), Err(HubtError::ConditionFailed { index: 2, key: key.to_vec() }));
+            assert!(hubt.store.nodes == nodes);
+        }
+        assert_eq!(hubt.get(b"d").unwrap(), None);
+
+        // Compared by leaf hash, so trees without values take them too
+        let mut plain = Hubt::new();
+        plain.batch_update(vec![Op::Insert(b"a".to_vec(), b"1".to_vec())]).unwrap();
+        plain.batch_update(vec![Op::CompareAndSwap { k: b"a".to_vec(), expected: b"1".to_vec(), v: b"2".to_vec() }]).unwrap();
+        assert!(plain.batch_update(vec![Op::CompareAndSwap { k: b"a".to_vec(), expected: b"1".to_vec(), v: b"3".to_vec() }]).is_err());
+    }
+
+    #[test]
+    fn test_repeated_keys_in_one_batch() {
+        let kv = |k: &[u8], v: &[u8]| (k.to_vec(), v.to_vec());
+        let mut hubt = ValueHubt::new();
+        hubt.batch_update(vec![Op::Insert(b"a".to_vec(), b"1".to_vec())]).unwrap();
+        let nodes = hubt.store.nodes.clone();
+
+        // Each op sees the leaf left by the ones before it
+        let twice = [
+            (vec![Op::InsertIfAbsent(b"b".to_vec(), b"1".to_vec()), Op::InsertIfAbsent(b"b".to_vec(), b"2".to_vec())], b"b"),
+            (vec![Op::DeleteIfPresent(b"a".to_vec()), Op::DeleteIfPresent(b"a".to_vec())], b"a"),
+            (vec![Op::Insert(b"a".to_vec(), b"2".to_vec()), Op::CompareAndSwap { k: b"a".to_vec(), expected: b"1".to_vec(), v: b"3".to_vec() }], b"a"),
+        ];
+        for (ops, key) in twice {
+            assert_eq!(hubt.batch_update(ops), Err(HubtError::ConditionFailed { index: 1, key: key.to_vec() }));
+            assert!(hubt.store.nodes == nodes);
+        }
+
+        let leaf = |k: &[u8], v: &[u8]| Sha256Hasher::leaf(k, v);
+        let result = hubt.batch_update(vec![
+            Op::CompareAndSwap { k: b"a".to_vec(), expected: b"1".to_vec(), v: b"2".to_vec() },
+            Op::CompareAndSwap { k: b"a".to_vec(), expected: b"2".to_vec(), v: b"3".to_vec() },
+            Op::Insert(b"a".to_vec(), b"4".to_vec()),
+            Op::InsertIfAbsent(b"b".to_vec(), b"1".to_vec()),
+            Op::DeleteIfPresent(b"b".to_vec()),
+            Op::Delete(b"b".to_vec()),
+        ]).unwrap();
+        assert_eq!(result.outcomes, vec![
+            OpOutcome::Updated { old: leaf(b"a", b"1"), new: leaf(b"a", b"2") },
+            OpOutcome::Updated { old: leaf(b"a", b"2"), new: leaf(b"a", b"3") },
+            OpOutcome::Updated { old: leaf(b"a", b"3"), new: leaf(b"a", b"4") },
+            OpOutcome::Inserted { new: leaf(b"b", b"1") },
+            OpOutcome::Deleted { old: leaf(b"b", b"1") },
+            OpOutcome::DeletedMissing,
+        ]);
+        // The last op on a key wins, a key inserted and deleted again is left out of `changed`
+        assert_eq!(hubt.get(b"a").unwrap(), Some(b"4".to_vec()));
+        assert_eq!(hubt.get(b"b").unwrap(), None);
+        assert_eq!(result.changed, vec![NodeKey { path: Sha256Hasher::hash(b"a"), len: 256 }]);
+
+        for _ in 0..10 {
+            let mut last = ValueHubt::new();
+            let ops = (0..50u8).map(|i| Op::Insert(b"k".to_vec(), vec![i])).collect();
+            last.batch_update(ops).unwrap();
+            assert_eq!(last.leaves().map(|l| l.unwrap().kv.unwrap()).collect::<Vec<_>>(), vec![kv(b"k", &[49])]);
+        }
+    }
+
     #[test]
     fn test_unified_proof_logic() {
         let mut hubt = Hubt::new();
@@ -513,20 +355,20 @@ mod tests {
         let v1 = b"100".to_vec();
 
         // Insert Key 1
-        hubt.batch_update(vec![Op::Insert(k1.clone(), v1.clone())]);
+        hubt.batch_update(vec![Op::Insert(k1.clone(), v1.clone())]).unwrap();
 
         // Case 1: Inclusion (Key exists, Value matches)
-        let proof_inc = hubt.prove(k1.clone());
+        let proof_inc = hubt.prove(k1.clone()).unwrap();
         assert_eq!(Hubt::verify(&proof_inc, k1.clone(), v1.clone()), VerifyStatus::Included);
 
         // Case 2: Mismatch (Key exists, Value differs)
         let v1_fake = b"999".to_vec();
-        let proof_mis = hubt.prove(k1.clone()); // Same proof generation!
+        let proof_mis = hubt.prove(k1.clone()).unwrap(); // Same proof generation!
         assert_eq!(Hubt::verify(&proof_mis, k1.clone(), v1_fake), VerifyStatus::Mismatch);
 
         // Case 3: Non-Existence (Key does not exist)
         let k_missing = b"user:999".to_vec();
-        let proof_non = hubt.prove(k_missing.clone());
+        let proof_non = hubt.prove(k_missing.clone()).unwrap();
         assert_eq!(Hubt::verify(&proof_non, k_missing, v1.clone()), VerifyStatus::NonExistence);
     }
 
@@ -538,11 +380,248 @@ mod tests {
             Op::Insert(b"A".to_vec(), b"1".to_vec()),
             Op::Insert(b"B".to_vec(), b"2".to_vec()),
             Op::Insert(b"C".to_vec(), b"3".to_vec()),
-        ]);
+        ]).unwrap();
 
         let k = b"B".to_vec();
-        let proof = hubt.prove(k);
-        assert!(Hubt::verify_integrity(&proof));
+        let proof = hubt.prove(k).unwrap();
+        assert!(proof.verify_integrity::<Sha256Hasher>());
+    }
+
+    #[test]
+    fn test_non_existence_soundness() {
+        let mut hubt = Hubt::new();
+        let empty = hubt.prove(b"k".to_vec()).unwrap();
+        assert_eq!(Hubt::verify(&empty, b"k".to_vec(), vec![]), VerifyStatus::NonExistence);
+
+        let keys: Vec<Vec<u8>> = (0..200u32).map(|i| format!("key:{}", i).into_bytes()).collect();
+        hubt.batch_update(keys.iter().map(|k| Op::Insert(k.clone(), b"v".to_vec())).collect()).unwrap();
+
+        for i in 0..200 {
+            let missing = format!("missing:{}", i).into_bytes();
+            let proof = hubt.prove(missing.clone()).unwrap();
+            assert_eq!(Hubt::verify(&proof, missing, vec![]), VerifyStatus::NonExistence);
+
+            // Reusing another key's proof to claim a present key is absent must fail.
+            let other = hubt.prove(keys[(i + 1) % 200].clone()).unwrap();
+            assert_eq!(Hubt::verify(&other, keys[i].clone(), b"v".to_vec()), VerifyStatus::Invalid);
+        }
+
+        let missing = b"missing:0".to_vec();
+        let proof = hubt.prove(missing.clone()).unwrap();
+        assert!(!proof.nodes.is_empty());
+
+        // Lengths that contradict the sibling order are rejected.
+        let mut forged = proof.clone();
+        forged.nodes[0].len = forged.len;
+        assert_eq!(Hubt::verify(&forged, missing.clone(), vec![]), VerifyStatus::Invalid);
+
+        let mut forged = proof.clone();
+        forged.nodes.reverse();
+        assert_eq!(Hubt::verify(&forged, missing.clone(), vec![]), VerifyStatus::Invalid);
+
+        // A found node whose subtree still contains the target proves nothing.
+        let mut forged = proof.clone();
+        forged.len = lcp_be(&forged.path, &hubt.mode().path::<Sha256Hasher>(&missing)).1;
+        mask_after_be(&mut forged.path, forged.len);
+        assert_eq!(Hubt::verify(&forged, missing, vec![]), VerifyStatus::Invalid);
+    }
+
+    #[test]
+    fn test_namespaced_hotpath_layout() {
+        let mut hubt: Hubt = Hubt::with_mode(KeyMode::Namespaced);
+        let ns_a = b"contract";
+        let ns_b = b"balances";
+        let key = |ns: &[u8; 8], i: u32| [ns.as_slice(), format!("/account/{}", i).as_bytes()].concat();
+
+        let mut ops = Vec::new();
+        for i in 0..50 {
+            ops.push(Op::Insert(key(ns_a, i), b"1".to_vec()));
+            ops.push(Op::Insert(key(ns_b, i), b"1".to_vec()));
+        }
+        hubt.batch_update(ops).unwrap();
+
+        for (k, _) in hubt.store.iter().filter(|(k, _)| k.len == 256) {
+            assert!(k.path[..8] == ns_a[..] || k.path[..8] == ns_b[..]);
+        }
+
+        // Touching one namespace must leave the other namespace's subtree untouched.
+        let before = hubt.store.clone();
+        hubt.batch_update(vec![
+            Op::Insert(key(ns_a, 7), b"2".to_vec()),
+            Op::Insert(key(ns_a, 99), b"1".to_vec()),
+            Op::Delete(key(ns_a, 3)),
+        ]).unwrap();
+        let changed = |k: &NodeKey| before.get(k) != hubt.store.get(k);
+        for k in before.keys().chain(hubt.store.keys()).filter(|k| changed(k)) {
+            assert!(k.len < 64 || k.path[..8] == ns_a[..], "node outside namespace changed: {:?}", k);
+        }
+
+        let k = key(ns_a, 7);
+        let proof = hubt.prove(k.clone()).unwrap();
+        assert_eq!(proof.path[..8], ns_a[..]);
+        assert_eq!(Hubt::<Sha256Hasher>::verify_with_mode(KeyMode::Namespaced, &proof, k.clone(), b"2".to_vec()), VerifyStatus::Included);
+        assert_ne!(Hubt::verify(&proof, k, b"2".to_vec()), VerifyStatus::Included);
+
+        let missing = key(ns_b, 1000);
+        let proof = hubt.prove(missing.clone()).unwrap();
+        assert_eq!(Hubt::<Sha256Hasher>::verify_with_mode(KeyMode::Namespaced, &proof, missing, b"1".to_vec()), VerifyStatus::NonExistence);
+    }
+
+    #[test]
+    fn test_pluggable_hashers() {
+        fn check<H: TreeHasher>() -> Hash {
+            let mut hubt = Hubt::<H>::default();
+            hubt.batch_update((0..20u32).map(|i| Op::Insert(i.to_be_bytes().to_vec(), b"v".to_vec())).collect()).unwrap();
+
+            let proof = hubt.prove(7u32.to_be_bytes().to_vec()).unwrap();
+            assert_eq!(proof.root, hubt.root().unwrap());
+            assert_eq!(Hubt::<H>::verify_with_mode(KeyMode::Hashed, &proof, 7u32.to_be_bytes().to_vec(), b"v".to_vec()), VerifyStatus::Included);
+            hubt.root().unwrap()
+        }
+
+        let roots = [check::<Sha256Hasher>(), check::<Blake3Hasher>(), check::<Keccak256Hasher>(), check::<PoseidonHasher>()];
+        for i in 0..roots.len() {
+            for j in (i + 1)..roots.len() {
+                assert_ne!(roots[i], roots[j]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_poseidon_circuit_vector() {
+        // The inclusion proof zkverify/circuits/merkle.circom checks, see fixtures/poseidon.txt
+        let word = |i: u32| [[0u8; 28].as_slice(), &i.to_be_bytes()].concat();
+        let hex = |h: &[u8]| h.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        let mut hubt = Hubt::<PoseidonHasher>::default();
+        hubt.batch_update((0..32).map(|i| Op::Insert(word(i), word(1000 + i))).collect()).unwrap();
+        let (k, v) = (word(5), word(1005));
+        let proof = hubt.prove(k.clone()).unwrap();
+        assert_eq!(Hubt::<PoseidonHasher>::verify_with_mode(KeyMode::Hashed, &proof, k.clone(), v.clone()), VerifyStatus::Included);
+
+        let mut lines = vec![format!("key {}", hex(&k)), format!("value {}", hex(&v)), format!("root {}", hex(&proof.root))];
+        lines.extend(proof.nodes.iter().map(|n| format!("node {} {}", n.direction, hex(&n.hash))));
+        let fixture = std::fs::read_to_string(format!("{}/fixtures/poseidon.txt", env!("CARGO_MANIFEST_DIR"))).unwrap();
+        let fixture: Vec<&str> = fixture.lines().filter(|line| !line.starts_with('#') && !line.trim().is_empty()).collect();
+        assert_eq!(fixture, lines);
+
+        // What the circuit does: leaf, then one node per sibling, no position binding
+        let acc = proof.nodes.iter().fold(PoseidonHasher::leaf(&k, &v), |acc, n| match n.direction {
+            0 => PoseidonHasher::node(&n.hash, &acc),
+            _ => PoseidonHasher::node(&acc, &n.hash),
+        });
+        assert_eq!(acc, proof.root);
+    }
+
+    /// Root of the sorted `(path, leaf_hash)` set, computed recursively from scratch.
+    fn reference_root(leaves: &[(Path, Hash)]) -> Hash {
+        match leaves {
+            [] => [0u8; 32],
+            [(_, h)] => *h,
+            _ => {
+                let (first, last) = (&leaves[0].0, &leaves[leaves.len() - 1].0);
+                let (_, depth) = lcp_be(first, last);
+                let split = leaves.iter().position(|(p, _)| get_bit_be(p, depth) == 1).unwrap();
+                Sha256Hasher::concat_and_hash(&reference_root(&leaves[..split]), &reference_root(&leaves[split..]))
+            }
+        }
+    }
+
+    #[test]
+    fn test_hardened_hashing() {
+        // Same vectors as the Elixir module in hardened mode
+        let hex = |h: &Hash| h.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        let mut hubt = Hubt::<Hardened<Sha256Hasher>>::default();
+        hubt.batch_update(vec![Op::Insert(b"0".to_vec(), b"0".to_vec()), Op::Insert(b"1".to_vec(), b"1".to_vec())]).unwrap();
+        assert_eq!(hex(&hubt.root().unwrap()), "5ab360d2234427edd42eb63ee4938e0bcb32369bc6956ddb85c88e8b6d47244e");
+        hubt.batch_update(vec![Op::Insert(b"2".to_vec(), b"2".to_vec())]).unwrap();
+        assert_eq!(hex(&hubt.root().unwrap()), "e790303b46adb7e35405f071e87c3835c6fb448459f99b3ae398271edf9782f9");
+
+        let ops: Vec<Op> = (0..1000u32).map(|i| Op::Insert(i.to_be_bytes().to_vec(), b"v".to_vec())).collect();
+        hubt.batch_update(ops.clone()).unwrap();
+        let mut plain = Hubt::new();
+        plain.batch_update(ops).unwrap();
+        assert_ne!(hubt.root().unwrap(), plain.root().unwrap());
+        assert!(hubt.check().unwrap().is_ok());
+
+        // Proofs only verify under the hasher that built them
+        let k = 7u32.to_be_bytes().to_vec();
+        let proof = hubt.prove(k.clone()).unwrap();
+        let verify = Hubt::<Hardened<Sha256Hasher>>::verify_with_mode;
+        assert_eq!(verify(KeyMode::Hashed, &proof, k.clone(), b"v".to_vec()), VerifyStatus::Included);
+        assert_eq!(verify(KeyMode::Hashed, &proof, k.clone(), b"w".to_vec()), VerifyStatus::Mismatch);
+        assert_eq!(verify(KeyMode::Hashed, &plain.prove(k.clone()).unwrap(), k.clone(), b"v".to_vec()), VerifyStatus::Invalid);
+        let kvs = vec![(k, b"v".to_vec()), (b"missing".to_vec(), b"v".to_vec())];
+        let statuses = Hubt::<Hardened<Sha256Hasher>>::verify_many_with_mode(KeyMode::Hashed, &hubt.prove_many(&[kvs[0].0.clone(), kvs[1].0.clone()]).unwrap(), &kvs);
+        assert_eq!(statuses, vec![VerifyStatus::Included, VerifyStatus::NonExistence]);
+
+        // The bulk loader builds the same nodes
+        let mut bulk = Hubt::<Hardened<Sha256Hasher>>::default();
+        let kvs: Vec<(Vec<u8>, Vec<u8>)> = (0..3u32).map(|i| (i.to_string().into_bytes(), i.to_string().into_bytes()))
+            .chain((0..1000u32).map(|i| (i.to_be_bytes().to_vec(), b"v".to_vec()))).collect();
+        bulk.bulk_load(kvs).unwrap();
+        assert!(bulk.store == hubt.store);
+    }
+
+    #[test]
+    fn test_incremental_matches_reference_root() {
+        let mut hubt = Hubt::new();
+        let mut model = BTreeMap::new();
+        let mut check = |hubt: &mut Hubt, ops: Vec<(bool, u32)>| {
+            let mut batch = vec![];
+            for (ins, i) in ops {
+                let k = i.to_string().into_bytes();
+                if ins {
+                    model.insert(Sha256Hasher::hash(&k), Sha256Hasher::concat_and_hash(&k, b"v"));
+                    batch.push(Op::Insert(k, b"v".to_vec()));
+                } else {
+                    model.remove(&Sha256Hasher::hash(&k));
+                    batch.push(Op::Delete(k));
+                }
+            }
+            hubt.batch_update(batch).unwrap();
+            let leaves: Vec<(Path, Hash)> = model.iter().map(|(p, h)| (*p, *h)).collect();
+            assert_eq!(hubt.root().unwrap(), reference_root(&leaves));
+        };
+
+        // Single inserts, pairs, deletes (present and missing) and overwrites
+        for i in 0..100 { check(&mut hubt, vec![(true, i)]); }
+        for i in 50..100 { check(&mut hubt, vec![(true, 2 * i), (true, 2 * i + 1)]); }
+        for i in (0..200).step_by(3) { check(&mut hubt, vec![(false, i)]); }
+        check(&mut hubt, vec![(false, 5000), (true, 1), (true, 7)]);
+        check(&mut hubt, (0..200).map(|i| (false, i)).collect());
+        assert_eq!(hubt.root().unwrap(), [0u8; 32]);
+    }
+
+    /// The same map without the parallel `map_nodes`.
+    struct Sequential(BTreeMap<NodeKey, Hash>);
+
+    impl HubtRead for Sequential {
+        fn get(&self, key: &NodeKey) -> Result<Option<Hash>> { HubtRead::get(&self.0, key) }
+        fn seek_prev(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>> { self.0.seek_prev(key) }
+        fn seek_next(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>> { self.0.seek_next(key) }
+    }
+
+    impl HubtStore for Sequential {
+        fn put(&mut self, key: NodeKey, hash: Hash) -> Result<()> { self.0.put(key, hash) }
+        fn delete(&mut self, key: &NodeKey) -> Result<()> { HubtStore::delete(&mut self.0, key) }
+    }
+
+    #[test]
+    fn test_parallel_rehash_matches_sequential() {
+        let mut hubt = Hubt::new();
+        let mut sequential = Tree::<Sequential>::with_store(Sequential(BTreeMap::new()), KeyMode::Hashed);
+        let batches: Vec<Vec<Op>> = vec![
+            (0..50_000u32).map(|i| Op::Insert(i.to_be_bytes().to_vec(), b"a".to_vec())).collect(),
+            (0..50_000u32).step_by(3).map(|i| Op::Delete(i.to_be_bytes().to_vec())).collect(),
+            (40_000..60_000u32).map(|i| Op::Insert(i.to_be_bytes().to_vec(), b"b".to_vec())).collect(),
+            vec![Op::Insert(1u32.to_be_bytes().to_vec(), b"c".to_vec()), Op::Delete(2u32.to_be_bytes().to_vec())],
+        ];
+        for batch in batches {
+            hubt.batch_update(batch.clone()).unwrap();
+            sequential.batch_update(batch).unwrap();
+            assert_eq!(hubt.root().unwrap(), sequential.root().unwrap());
+            assert!(hubt.store == sequential.store.0);
+        }
     }
 
     #[test]
@@ -559,7 +638,7 @@ mod tests {
         }
 
         let t_fill = Instant::now();
-        hubt.batch_update(ops);
+        hubt.batch_update(ops).unwrap();
         println!("Pre-fill complete in {:?}\n", t_fill.elapsed());
 
         // 2. INCREMENTAL BATCHES: Start from 1,000,000 upwards
@@ -580,7 +659,7 @@ mod tests {
 
             // Measure ONLY the update time
             let start = Instant::now();
-            hubt.batch_update(ops);
+            hubt.batch_update(ops).unwrap();
             let duration = start.elapsed();
 
             let range_str = format!("{} .. {}", key_cursor, key_cursor + count);
@@ -608,7 +687,7 @@ mod tests {
         }
 
         let t_fill = Instant::now();
-        hubt.batch_update(ops);
+        hubt.batch_update(ops).unwrap();
         println!("Tree filled (100k items) in: {:?}", t_fill.elapsed());
 
         // 2. Measure Proof Generation
@@ -620,7 +699,7 @@ mod tests {
             let k = s.as_bytes().to_vec();
 
             // Generate the proof (unwrap ensures it exists)
-            let _proof = hubt.prove(k);
+            let _proof = hubt.prove(k).unwrap();
         }
 
         let duration = t_proof_start.elapsed();
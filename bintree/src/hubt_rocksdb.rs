@@ -1,447 +1,960 @@
-use rocksdb::{ColumnFamily, Transaction, DB};
-use sha2::{Digest, Sha256};
+use crate::bulk::{internal_nodes, sorted_leaves};
+use crate::error::Result;
+use crate::hasher::{KeyMode, TreeHasher, ZERO_HASH};
+use crate::store::{HubtRead, HubtStore};
+use crate::tree::Tree;
+use crate::values::{LeafValues, WithValues};
+use crate::versioned::{PrunePolicy, PruneState, VersionedBackend, VersionedKey, VersionedStore};
 use rayon::prelude::*;
-use std::cmp::Ordering;
-use std::collections::BTreeSet;
+use rocksdb::{ColumnFamily, Options, SstFileWriter, Transaction, TransactionDB, DB};
 use std::convert::TryInto;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
 // ============================================================================
 // TYPES
 // ============================================================================
 
-pub type Hash = [u8; 32];
-pub type Path = [u8; 32];
-const ZERO_HASH: Hash = [0u8; 32];
+pub use crate::bits::Path;
+pub use crate::changes::ChangeSet;
+pub use crate::check::{CheckReport, Problem};
+pub use crate::diff::Diff;
+pub use crate::error::HubtError;
+pub use crate::hasher::{Hardened, Hash, Sha256Hasher};
+pub use crate::multiproof::{FoundNode, MultiProof, MultiStep};
+pub use crate::proof::{Proof, ProofNode, VerifyStatus};
+pub use crate::scan::Leaf;
+pub use crate::snapshot::{ImportState, SnapshotChunk};
+pub use crate::store::NodeKey;
+pub use crate::subtree::SubtreeProof;
+pub use crate::tree::{BatchResult, Op, OpOutcome};
+pub use crate::wire::Wire;
 
-#[derive(Debug, Clone, PartialEq, Eq, Copy)]
-pub struct NodeKey {
-    pub path: Path,
-    pub len: u16,
-}
+// ============================================================================
+// ROCKSDB SERIALIZATION HELPERS
+// ============================================================================
 
-// Big Endian Sort: Path first, then Length
-impl PartialOrd for NodeKey {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
+// Serialize: Path (32 bytes) + Len (2 bytes Big Endian)
+#[inline]
+fn serialize_key(key: &NodeKey) -> Vec<u8> {
+    let mut v = Vec::with_capacity(34);
+    v.extend_from_slice(&key.path);
+    v.extend_from_slice(&key.len.to_be_bytes());
+    v
 }
 
-impl Ord for NodeKey {
-    fn cmp(&self, other: &Self) -> Ordering {
-        match self.path.cmp(&other.path) {
-            Ordering::Equal => self.len.cmp(&other.len),
-            other => other,
-        }
+#[inline]
+fn deserialize_key(data: &[u8]) -> Result<NodeKey> {
+    if data.len() != 34 {
+        return Err(HubtError::BadKeyLength(data.to_vec()));
     }
+    let mut path = [0u8; 32];
+    path.copy_from_slice(&data[0..32]);
+    let len = u16::from_be_bytes([data[32], data[33]]);
+    if len > 256 {
+        return Err(HubtError::BadKeyLength(data.to_vec()));
+    }
+    Ok(NodeKey { path, len })
 }
 
-// Added Clone just in case, though into_par_iter handles ownership
-#[derive(Debug, Clone)]
-pub enum Op {
-    Insert(Vec<u8>, Vec<u8>),
-    Delete(Vec<u8>),
+#[inline]
+fn deserialize_hash(key: &[u8], data: &[u8]) -> Result<Hash> {
+    data.try_into().map_err(|_| HubtError::CorruptNode { key: key.to_vec(), len: data.len() })
 }
 
-#[derive(Debug, Clone)]
-pub struct ProofNode { pub hash: Hash, pub direction: u8, pub len: u16 }
-
-#[derive(Debug)]
-pub struct Proof { pub root: Hash, pub nodes: Vec<ProofNode> }
+#[inline]
+fn deserialize_entry(key: &[u8], value: &[u8]) -> Result<(NodeKey, Hash)> {
+    Ok((deserialize_key(key)?, deserialize_hash(key, value)?))
+}
 
 // ============================================================================
-// BIT & HASH HELPERS
+// ROCKSDB STORE
 // ============================================================================
 
-#[inline(always)]
-fn get_bit_be(data: &[u8], index: u16) -> u8 {
-    if index >= 256 { return 0; }
-    let byte_idx = (index >> 3) as usize;
-    let bit_offset = 7 - (index & 7);
-    (data[byte_idx] >> bit_offset) & 1
+/// `HubtStore` over one column family, read and written through a transaction.
+pub struct RocksStore<'a, D = DB> {
+    txn: &'a Transaction<'a, D>,
+    cf: &'a ColumnFamily,
 }
 
-#[inline(always)]
-fn set_bit_be(data: &mut [u8], index: u16, val: u8) {
-    if index >= 256 { return; }
-    let byte_idx = (index >> 3) as usize;
-    let bit_offset = 7 - (index & 7);
-    if val == 1 { data[byte_idx] |= 1 << bit_offset; }
-    else { data[byte_idx] &= !(1 << bit_offset); }
+impl<'a, D> RocksStore<'a, D> {
+    pub fn new(txn: &'a Transaction<'a, D>, cf: &'a ColumnFamily) -> Self {
+        Self { txn, cf }
+    }
 }
 
-#[inline]
-fn mask_after_be(data: &mut [u8], len: u16) {
-    if len >= 256 { return; }
-    let byte_idx = (len >> 3) as usize;
-    let start_clean_bit = len;
-    for i in start_clean_bit..((byte_idx as u16 + 1) << 3) {
-        let off = 7 - (i & 7);
-        data[byte_idx] &= !(1 << off);
+impl<'a, D> HubtRead for RocksStore<'a, D> {
+    fn get(&self, key: &NodeKey) -> Result<Option<Hash>> {
+        let k = serialize_key(key);
+        match self.txn.get_cf(self.cf, &k)? {
+            Some(v) => Ok(Some(deserialize_hash(&k, &v)?)),
+            None => Ok(None),
+        }
     }
-    if byte_idx + 1 < 32 { data[(byte_idx + 1)..].fill(0); }
-}
 
-#[inline]
-fn lcp_be(p1: &Path, p2: &Path) -> (Path, u16) {
-    let mut len = 0;
-    let mut byte_idx = 0;
-    while byte_idx < 32 && p1[byte_idx] == p2[byte_idx] {
-        len += 8;
-        byte_idx += 1;
-    }
-    if byte_idx < 32 {
-        for i in 0..8 {
-            let idx = (byte_idx << 3) + i;
-            if get_bit_be(p1, idx as u16) == get_bit_be(p2, idx as u16) { len += 1; }
-            else { break; }
+    fn seek_prev(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>> {
+        let k_bytes = serialize_key(key);
+        let mut iter = self.txn.raw_iterator_cf(self.cf);
+        iter.seek_for_prev(&k_bytes);
+
+        if iter.key() == Some(k_bytes.as_slice()) {
+            iter.prev();
+        }
+
+        match (iter.key(), iter.value()) {
+            (Some(k), Some(v)) => Ok(Some(deserialize_entry(k, v)?)),
+            _ => {
+                iter.status()?;
+                Ok(None)
+            }
+        }
+    }
+
+    fn seek_next(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>> {
+        let k_bytes = serialize_key(key);
+        let mut iter = self.txn.raw_iterator_cf(self.cf);
+        iter.seek(k_bytes);
+
+        match (iter.key(), iter.value()) {
+            (Some(k), Some(v)) => Ok(Some(deserialize_entry(k, v)?)),
+            _ => {
+                iter.status()?;
+                Ok(None)
+            }
         }
     }
-    let mut prefix = *p1;
-    mask_after_be(&mut prefix, len);
-    (prefix, len)
+
 }
 
-#[inline]
-fn prefix_match_be(target: &Path, path: &Path, len: u16) -> bool {
-    let full_bytes = (len >> 3) as usize;
-    if target[..full_bytes] != path[..full_bytes] { return false; }
-    let rem = len & 7;
-    if rem > 0 {
-        let mask = 0xFF << (8 - rem);
-        if (target[full_bytes] & mask) != (path[full_bytes] & mask) { return false; }
+impl<'a, D> HubtStore for RocksStore<'a, D> {
+    fn put(&mut self, key: NodeKey, hash: Hash) -> Result<()> {
+        let k = serialize_key(&key);
+        self.txn.put_cf(self.cf, k, hash)?;
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &NodeKey) -> Result<()> {
+        let k = serialize_key(key);
+        self.txn.delete_cf(self.cf, k)?;
+        Ok(())
     }
-    true
 }
 
+// ============================================================================
+// ROCKSDB VALUES
+// ============================================================================
+
+// Values column family layout: Path (32) -> Key Len (4 BE) || Key || Value
 #[inline]
-fn sha256(data: &[u8]) -> Hash {
-    let mut hasher = Sha256::new();
-    hasher.update(data);
-    hasher.finalize().into()
+fn serialize_value(k: &[u8], v: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(4 + k.len() + v.len());
+    out.extend_from_slice(&(k.len() as u32).to_be_bytes());
+    out.extend_from_slice(k);
+    out.extend_from_slice(v);
+    out
 }
 
 #[inline]
-fn concat_and_hash(a: &[u8], b: &[u8]) -> Hash {
-    let mut hasher = Sha256::new();
-    hasher.update(a);
-    hasher.update(b);
-    hasher.finalize().into()
+fn deserialize_value(path: &Path, data: &[u8]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let corrupt = || HubtError::CorruptValue(path.to_vec());
+    let k_len = u32::from_be_bytes(data.get(0..4).ok_or_else(corrupt)?.try_into().unwrap()) as usize;
+    let k = data.get(4..4 + k_len).ok_or_else(corrupt)?;
+    Ok((k.to_vec(), data[4 + k_len..].to_vec()))
+}
+
+/// `LeafValues` over its own column family, read and written through a transaction.
+pub struct RocksValues<'a, D = DB> {
+    txn: &'a Transaction<'a, D>,
+    cf: &'a ColumnFamily,
+}
+
+impl<'a, D> RocksValues<'a, D> {
+    pub fn new(txn: &'a Transaction<'a, D>, cf: &'a ColumnFamily) -> Self {
+        Self { txn, cf }
+    }
+}
+
+impl<'a, D> LeafValues for RocksValues<'a, D> {
+    fn get(&self, path: &Path) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        match self.txn.get_cf(self.cf, path)? {
+            Some(data) => Ok(Some(deserialize_value(path, &data)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&mut self, path: Path, k: &[u8], v: &[u8]) -> Result<()> {
+        self.txn.put_cf(self.cf, path, serialize_value(k, v))?;
+        Ok(())
+    }
+
+    fn delete(&mut self, path: &Path) -> Result<()> {
+        self.txn.delete_cf(self.cf, path)?;
+        Ok(())
+    }
 }
 
 // ============================================================================
-// ROCKSDB SERIALIZATION HELPERS
+// ROCKSDB VERSIONED BACKEND
 // ============================================================================
 
-// Serialize: Path (32 bytes) + Len (2 bytes Big Endian)
+// Versioned column family layout:
+// 'n' || Path (32) || Len (2 BE) || Version (8 BE) -> Hash (32), or empty for a delete
+// "mhead" -> latest committed version (8 BE)
+// "mprune" -> floor (8 BE) || has cursor (1) || cursor key (34, if set) || kept versions (8 BE each)
+const VERSIONED_NODE_PREFIX: u8 = b'n';
+const VERSIONED_HEAD_KEY: &[u8] = b"mhead";
+const VERSIONED_PRUNE_KEY: &[u8] = b"mprune";
+
 #[inline]
-fn serialize_key(key: &NodeKey) -> Vec<u8> {
-    let mut v = Vec::with_capacity(34);
-    v.extend_from_slice(&key.path);
-    v.extend_from_slice(&key.len.to_be_bytes());
+fn serialize_versioned_key(key: &VersionedKey) -> Vec<u8> {
+    let mut v = Vec::with_capacity(43);
+    v.push(VERSIONED_NODE_PREFIX);
+    v.extend_from_slice(&serialize_key(&key.node));
+    v.extend_from_slice(&key.version.to_be_bytes());
     v
 }
 
 #[inline]
-fn deserialize_key(data: &[u8]) -> NodeKey {
-    let mut path = [0u8; 32];
-    path.copy_from_slice(&data[0..32]);
-    let len = u16::from_be_bytes([data[32], data[33]]);
-    NodeKey { path, len }
+fn deserialize_versioned_entry(key: &[u8], value: &[u8]) -> Result<(VersionedKey, Option<Hash>)> {
+    if key.len() != 43 || key[0] != VERSIONED_NODE_PREFIX {
+        return Err(HubtError::BadKeyLength(key.to_vec()));
+    }
+    let node = deserialize_key(&key[1..35])?;
+    let version = u64::from_be_bytes(key[35..43].try_into().unwrap());
+    let hash = if value.is_empty() { None } else { Some(deserialize_hash(key, value)?) };
+    Ok((VersionedKey { node, version }, hash))
 }
 
-// ============================================================================
-// ROCKSDB HUBT
-// ============================================================================
+fn serialize_prune_state(state: &PruneState) -> Vec<u8> {
+    let mut v = Vec::with_capacity(9 + 34 + state.kept.len() * 8);
+    v.extend_from_slice(&state.floor.to_be_bytes());
+    match &state.cursor {
+        Some(cursor) => {
+            v.push(1);
+            v.extend_from_slice(&serialize_key(cursor));
+        }
+        None => v.push(0),
+    }
+    for version in &state.kept {
+        v.extend_from_slice(&version.to_be_bytes());
+    }
+    v
+}
+
+fn deserialize_prune_state(data: &[u8]) -> Result<PruneState> {
+    let corrupt = || HubtError::CorruptNode { key: VERSIONED_PRUNE_KEY.to_vec(), len: data.len() };
+    if data.len() < 9 {
+        return Err(corrupt());
+    }
+    let floor = u64::from_be_bytes(data[0..8].try_into().unwrap());
+    let (cursor, rest) = match data[8] {
+        0 => (None, &data[9..]),
+        // Sweep position, may be one past a leaf (len 257)
+        1 if data.len() >= 43 => {
+            let path = data[9..41].try_into().unwrap();
+            let len = u16::from_be_bytes([data[41], data[42]]);
+            (Some(NodeKey { path, len }), &data[43..])
+        }
+        _ => return Err(corrupt()),
+    };
+    if rest.len() % 8 != 0 {
+        return Err(corrupt());
+    }
+    let kept = rest.chunks(8).map(|c| u64::from_be_bytes(c.try_into().unwrap())).collect();
+    Ok(PruneState { floor, kept, cursor })
+}
 
-pub struct RocksHubt<'a> {
-    txn: &'a Transaction<'a, DB>,
+/// `VersionedBackend` over one column family, read and written through a transaction.
+pub struct RocksVersions<'a, D = DB> {
+    txn: &'a Transaction<'a, D>,
     cf: &'a ColumnFamily,
 }
 
-impl<'a> RocksHubt<'a> {
-    pub fn new(txn: &'a Transaction<'a, DB>, cf: &'a ColumnFamily) -> Self {
+impl<'a, D> RocksVersions<'a, D> {
+    pub fn new(txn: &'a Transaction<'a, D>, cf: &'a ColumnFamily) -> Self {
         Self { txn, cf }
     }
+}
 
-    pub fn root(&self) -> Hash {
-        // Find smallest node (Root is usually 00..00 len 0, or smallest leaf)
+impl<'a, D> VersionedBackend for RocksVersions<'a, D> {
+    fn entry_prev(&self, key: &VersionedKey) -> Result<Option<(VersionedKey, Option<Hash>)>> {
+        let k_bytes = serialize_versioned_key(key);
         let mut iter = self.txn.raw_iterator_cf(self.cf);
-        iter.seek_to_first();
-        if iter.valid() {
-            iter.value().unwrap().try_into().unwrap()
-        } else {
-            ZERO_HASH
+        iter.seek_for_prev(&k_bytes);
+
+        if iter.key() == Some(k_bytes.as_slice()) {
+            iter.prev();
         }
-    }
 
-    pub fn batch_update(&mut self, ops: Vec<Op>) {
-        // 1. Prepare Ops (Parallel Hash)
-        // Ensure 'rayon' is in Cargo.toml dependencies!
-        let mut prepared: Vec<(bool, Path, Hash)> = ops.into_par_iter().map(|op| {
-            match op {
-                Op::Insert(k, v) => (true, sha256(&k), concat_and_hash(&k, &v)),
-                Op::Delete(k) => (false, sha256(&k), ZERO_HASH)
+        match (iter.key(), iter.value()) {
+            (Some(k), Some(v)) if k.first() == Some(&VERSIONED_NODE_PREFIX) => Ok(Some(deserialize_versioned_entry(k, v)?)),
+            (Some(_), Some(_)) => Ok(None),
+            _ => {
+                iter.status()?;
+                Ok(None)
             }
-        }).collect();
-
-        prepared.par_sort_unstable_by(|a, b| a.1.cmp(&b.1));
+        }
+    }
 
-        // 2. Write to DB (Optimistic Logic)
-        let mut dirty_set = BTreeSet::new();
+    fn entry_next(&self, key: &VersionedKey) -> Result<Option<(VersionedKey, Option<Hash>)>> {
+        let k_bytes = serialize_versioned_key(key);
+        let mut iter = self.txn.raw_iterator_cf(self.cf);
+        iter.seek(k_bytes);
 
-        // A. Remove Old Leaves
-        for (is_ins, p, _) in &prepared {
-            if !*is_ins {
-                let key = NodeKey { path: *p, len: 256 };
-                self.remove_raw(&key);
+        match (iter.key(), iter.value()) {
+            (Some(k), Some(v)) if k.first() == Some(&VERSIONED_NODE_PREFIX) => Ok(Some(deserialize_versioned_entry(k, v)?)),
+            (Some(_), Some(_)) => Ok(None),
+            _ => {
+                iter.status()?;
+                Ok(None)
             }
         }
+    }
 
-        // B. Insert New Leaves
-        for (is_ins, p, l) in &prepared {
-            if *is_ins {
-                let key = NodeKey { path: *p, len: 256 };
-                self.insert_raw(key, *l);
-                dirty_set.insert(key);
-            }
+    fn put_entry(&mut self, key: VersionedKey, value: Option<Hash>) -> Result<()> {
+        let k = serialize_versioned_key(&key);
+        match value {
+            Some(hash) => self.txn.put_cf(self.cf, k, hash)?,
+            None => self.txn.put_cf(self.cf, k, [])?,
         }
+        Ok(())
+    }
 
-        // C. Calculate & Insert Split Points (Skeleton)
-        for window in prepared.windows(2) {
-            let (lcp_p, lcp_len) = lcp_be(&window[0].1, &window[1].1);
-            let key = NodeKey { path: lcp_p, len: lcp_len };
-            self.ensure_node_exists(key, &mut dirty_set);
-        }
+    fn delete_entry(&mut self, key: &VersionedKey) -> Result<()> {
+        self.txn.delete_cf(self.cf, serialize_versioned_key(key))?;
+        Ok(())
+    }
 
-        // Neighbors
-        for (is_ins, p, _) in &prepared {
-            if *is_ins {
-                self.ensure_split_points(*p, &mut dirty_set);
-            }
+    fn head(&self) -> Result<u64> {
+        match self.txn.get_cf(self.cf, VERSIONED_HEAD_KEY)? {
+            None => Ok(0),
+            Some(v) => match v.as_slice().try_into() {
+                Ok(bytes) => Ok(u64::from_be_bytes(bytes)),
+                Err(_) => Err(HubtError::CorruptNode { key: VERSIONED_HEAD_KEY.to_vec(), len: v.len() }),
+            },
         }
+    }
 
-        // D. Mark Ancestors Dirty
-        for (_, p, _) in &prepared {
-            self.collect_dirty_ancestors(*p, &mut dirty_set);
+    fn set_head(&mut self, version: u64) -> Result<()> {
+        self.txn.put_cf(self.cf, VERSIONED_HEAD_KEY, version.to_be_bytes())?;
+        Ok(())
+    }
+
+    fn prune_state(&self) -> Result<PruneState> {
+        match self.txn.get_cf(self.cf, VERSIONED_PRUNE_KEY)? {
+            None => Ok(PruneState::default()),
+            Some(v) => deserialize_prune_state(&v),
         }
+    }
 
-        // 3. Rehash Bottom-Up
-        self.rehash_and_prune(dirty_set);
+    fn set_prune_state(&mut self, state: &PruneState) -> Result<()> {
+        self.txn.put_cf(self.cf, VERSIONED_PRUNE_KEY, serialize_prune_state(state))?;
+        Ok(())
     }
+}
 
-    // --- HELPER LOGIC ---
+/// Prunes a versioned column family from its own thread, one transaction per step,
+/// so pruning never holds up the writer's `batch_update` / `commit`.
+///
+/// Writers only add entries for their pending version, which pruning never touches.
+/// A step that hits a lock conflict (`HubtError::Busy`) is retried with backoff,
+/// any other error ends the pruner.
+pub struct BackgroundPruner {
+    stop: Arc<AtomicBool>,
+    last_error: Arc<Mutex<Option<HubtError>>>,
+    thread: JoinHandle<Result<()>>,
+}
 
-    fn insert_raw(&mut self, key: NodeKey, val: Hash) {
-        let k = serialize_key(&key);
-        let _ = self.txn.put_cf(self.cf, k, val);
+const PRUNE_RETRY_MIN: Duration = Duration::from_millis(1);
+const PRUNE_RETRY_MAX: Duration = Duration::from_secs(1);
+
+impl BackgroundPruner {
+    /// Prunes up to `step` nodes per transaction, idling `idle` once a sweep is done.
+    pub fn spawn(db: Arc<TransactionDB>, cf_name: &str, policy: PrunePolicy, step: usize, idle: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let last_error = Arc::new(Mutex::new(None));
+        let (stopped, errors) = (stop.clone(), last_error.clone());
+        let cf_name = cf_name.to_string();
+        let thread = thread::spawn(move || {
+            let fail = |e: HubtError| {
+                *errors.lock().unwrap() = Some(e.clone());
+                Err(e)
+            };
+            let cf = match db.cf_handle(&cf_name) {
+                Some(cf) => cf,
+                None => return fail(HubtError::Storage(format!("missing column family {}", cf_name))),
+            };
+            let mut retry = PRUNE_RETRY_MIN;
+            while !stopped.load(AtomicOrdering::Relaxed) {
+                let txn = db.transaction();
+                let progress = VersionedStore::open(RocksVersions::new(&txn, cf))
+                    .and_then(|mut store| store.prune(&policy, step))
+                    .and_then(|progress| {
+                        txn.commit()?;
+                        Ok(progress)
+                    });
+                match progress {
+                    Ok(progress) => {
+                        retry = PRUNE_RETRY_MIN;
+                        if progress.done {
+                            thread::park_timeout(idle);
+                        }
+                    }
+                    Err(e @ HubtError::Busy(_)) => {
+                        *errors.lock().unwrap() = Some(e);
+                        thread::park_timeout(retry);
+                        retry = (retry * 2).min(PRUNE_RETRY_MAX);
+                    }
+                    Err(e) => return fail(e),
+                }
+            }
+            Ok(())
+        });
+        Self { stop, last_error, thread }
     }
 
-    fn remove_raw(&mut self, key: &NodeKey) {
-        let k = serialize_key(key);
-        let _ = self.txn.delete_cf(self.cf, k);
+    /// False once the pruner ended, stopped or on an error it does not retry.
+    pub fn is_alive(&self) -> bool {
+        !self.thread.is_finished()
     }
 
-    fn exists_raw(&self, key: &NodeKey) -> bool {
-        let k = serialize_key(key);
-        self.txn.get_cf(self.cf, k).unwrap().is_some()
+    /// The last error a step hit, a retried conflict or the one that ended the pruner.
+    pub fn last_error(&self) -> Option<HubtError> {
+        self.last_error.lock().unwrap().clone()
     }
 
-    fn ensure_node_exists(&mut self, key: NodeKey, dirty: &mut BTreeSet<NodeKey>) {
-        if !self.exists_raw(&key) {
-            self.insert_raw(key, ZERO_HASH);
-            dirty.insert(key);
-        }
+    /// Stops after the current step, returning the error that ended the pruner early, if any.
+    pub fn stop(self) -> Result<()> {
+        self.stop.store(true, AtomicOrdering::Relaxed);
+        self.thread.thread().unpark();
+        self.thread.join().map_err(|_| HubtError::Storage("pruner thread panicked".to_string()))?
     }
+}
+
+// ============================================================================
+// ROCKSDB HUBT
+// ============================================================================
 
-    fn ensure_split_points(&mut self, path: Path, dirty: &mut BTreeSet<NodeKey>) {
-        let key = NodeKey { path, len: 256 };
+/// Disk backed HUBT: the tree engine over a RocksDB transaction.
+pub type RocksHubt<'a, H = Sha256Hasher, D = DB> = Tree<RocksStore<'a, D>, H>;
 
-        // Seek Prev
-        if let Some((n_key, _)) = self.seek_prev(&key) {
-            if n_key.len == 256 {
-                let (lcp_p, lcp_l) = lcp_be(&path, &n_key.path);
-                self.ensure_node_exists(NodeKey { path: lcp_p, len: lcp_l }, dirty);
-            }
-        }
-        // Seek Next
-        if let Some((n_key, _)) = self.seek_next(&key) {
-            if n_key.len == 256 {
-                let (lcp_p, lcp_l) = lcp_be(&path, &n_key.path);
-                self.ensure_node_exists(NodeKey { path: lcp_p, len: lcp_l }, dirty);
-            }
-        }
+impl<'a, D> RocksHubt<'a, Sha256Hasher, D> {
+    pub fn new(txn: &'a Transaction<'a, D>, cf: &'a ColumnFamily) -> Self {
+        Self::with_hasher(txn, cf)
     }
+}
 
-    fn collect_dirty_ancestors(&self, target_path: Path, dirty: &mut BTreeSet<NodeKey>) {
-        let mut cursor = NodeKey { path: target_path, len: 256 };
+impl<'a> RocksHubt<'a> {
+    /// Verifies a proof from either backend, see `Proof::verify`.
+    pub fn verify(proof: &Proof, k: Vec<u8>, v: Vec<u8>) -> VerifyStatus {
+        Self::verify_with_mode(KeyMode::Hashed, proof, k, v)
+    }
 
-        loop {
-            // "seek_prev" finds node <= cursor
-            match self.seek_prev(&cursor) {
-                None => break,
-                Some((k, _)) => {
-                    let is_same = k == cursor;
+    /// Verifies a multiproof from either backend, see `MultiProof::verify`.
+    pub fn verify_many(proof: &MultiProof, kvs: &[(Vec<u8>, Vec<u8>)]) -> Vec<VerifyStatus> {
+        Self::verify_many_with_mode(KeyMode::Hashed, proof, kvs)
+    }
+}
 
-                    if prefix_match_be(&target_path, &k.path, k.len) {
-                        dirty.insert(k);
-                        // Move cursor strictly before K to continue up
-                        if k.len > 0 {
-                            cursor = NodeKey { path: k.path, len: k.len - 1 };
-                        } else {
-                            break; // Root reached
-                        }
-                    } else {
-                        // Mismatch -> Jump
-                        let (lcp_path, lcp_len) = lcp_be(&target_path, &k.path);
-                        let jump_key = NodeKey { path: lcp_path, len: lcp_len + 1 };
-
-                        if jump_key < k {
-                            cursor = jump_key;
-                        } else {
-                            if is_same {
-                                if k.len > 0 { cursor = NodeKey{path: k.path, len: k.len - 1}; } else { break; }
-                            } else {
-                                cursor = k;
-                            }
-                        }
-                    }
-                }
-            }
-        }
+impl<'a, H: TreeHasher, D> RocksHubt<'a, H, D> {
+    /// Opens the tree with a non default hasher, e.g. `RocksHubt::<Keccak256Hasher>::with_hasher(txn, cf)`.
+    pub fn with_hasher(txn: &'a Transaction<'a, D>, cf: &'a ColumnFamily) -> Self {
+        Self::with_mode(txn, cf, KeyMode::Hashed)
     }
 
-    fn rehash_and_prune(&mut self, dirty_nodes: BTreeSet<NodeKey>) {
-        let mut sorted_nodes: Vec<NodeKey> = dirty_nodes.into_iter().collect();
-        // Bottom-up sort
-        sorted_nodes.sort_unstable_by(|a, b| b.len.cmp(&a.len));
+    pub fn with_mode(txn: &'a Transaction<'a, D>, cf: &'a ColumnFamily, mode: KeyMode) -> Self {
+        Self::with_store(RocksStore::new(txn, cf), mode)
+    }
+}
 
-        for node in sorted_nodes {
-            if node.len == 256 { continue; }
+/// Disk backed HUBT that also keeps the key/value bytes of each leaf in `values_cf`, see `get`.
+pub type ValueRocksHubt<'a, H = Sha256Hasher, D = DB> = Tree<WithValues<RocksStore<'a, D>, RocksValues<'a, D>>, H>;
 
-            // Left Child (0)
-            let mut l_path = node.path;
-            set_bit_be(&mut l_path, node.len, 0);
-            mask_after_be(&mut l_path, node.len + 1);
-            let l_key = NodeKey { path: l_path, len: node.len + 1 };
+impl<'a, D> ValueRocksHubt<'a, Sha256Hasher, D> {
+    pub fn new(txn: &'a Transaction<'a, D>, cf: &'a ColumnFamily, values_cf: &'a ColumnFamily) -> Self {
+        Self::with_mode(txn, cf, values_cf, KeyMode::Hashed)
+    }
+}
 
-            let l_hash = self.seek_next(&l_key)
-                .filter(|(k, _)| prefix_match_be(&k.path, &l_path, node.len + 1))
-                .map(|(_, h)| h).unwrap_or(ZERO_HASH);
+impl<'a, H: TreeHasher, D> ValueRocksHubt<'a, H, D> {
+    pub fn with_mode(txn: &'a Transaction<'a, D>, cf: &'a ColumnFamily, values_cf: &'a ColumnFamily, mode: KeyMode) -> Self {
+        let store = WithValues { nodes: RocksStore::new(txn, cf), values: RocksValues::new(txn, values_cf) };
+        Self::with_store(store, mode)
+    }
+}
 
-            // Right Child (1)
-            let mut r_path = node.path;
-            set_bit_be(&mut r_path, node.len, 1);
-            mask_after_be(&mut r_path, node.len + 1);
-            let r_key = NodeKey { path: r_path, len: node.len + 1 };
+/// Disk backed HUBT keeping every committed version, see `commit`, `root_at` and `prove_at`.
+///
+/// Needs its own column family, the layout differs from `RocksHubt`.
+pub type VersionedRocksHubt<'a, H = Sha256Hasher, D = DB> = Tree<VersionedStore<RocksVersions<'a, D>>, H>;
 
-            let r_hash = self.seek_next(&r_key)
-                .filter(|(k, _)| prefix_match_be(&k.path, &r_path, node.len + 1))
-                .map(|(_, h)| h).unwrap_or(ZERO_HASH);
+impl<'a, D> VersionedRocksHubt<'a, Sha256Hasher, D> {
+    /// Opens the tree at the latest version committed to `cf`.
+    pub fn open(txn: &'a Transaction<'a, D>, cf: &'a ColumnFamily) -> Result<Self> {
+        Self::open_with_mode(txn, cf, KeyMode::Hashed)
+    }
+}
 
-            if l_hash != ZERO_HASH && r_hash != ZERO_HASH {
-                self.insert_raw(node, concat_and_hash(&l_hash, &r_hash));
-            } else {
-                self.remove_raw(&node);
-            }
-        }
+impl<'a, H: TreeHasher, D> VersionedRocksHubt<'a, H, D> {
+    pub fn open_with_mode(txn: &'a Transaction<'a, D>, cf: &'a ColumnFamily, mode: KeyMode) -> Result<Self> {
+        Ok(Self::with_store(VersionedStore::open(RocksVersions::new(txn, cf))?, mode))
     }
+}
 
-    // --- ITERATOR WRAPPERS ---
+// ============================================================================
+// ROCKSDB BULK LOAD
+// ============================================================================
 
-    fn seek_prev(&self, key: &NodeKey) -> Option<(NodeKey, Hash)> {
-        let k_bytes = serialize_key(key);
-        let mut iter = self.txn.raw_iterator_cf(self.cf);
-        iter.seek_for_prev(k_bytes);
-
-        if iter.valid() {
-            let found_k = deserialize_key(iter.key().unwrap());
-            let found_v: Hash = iter.value().unwrap().try_into().unwrap();
-            Some((found_k, found_v))
-        } else {
-            None
+/// Writes the whole tree over `kvs` as SST files for `DB::ingest_external_file_cf`, see `Tree::bulk_load`.
+///
+/// `nodes_file` is for the tree's column family, `values_file` for the values column family of a
+/// `ValueRocksHubt`. `opts` must match the column families. Returns the root, nothing is written
+/// for an empty `kvs` as RocksDB rejects empty SST files.
+pub fn write_sst<H: TreeHasher>(
+    opts: &Options,
+    mode: KeyMode,
+    kvs: Vec<(Vec<u8>, Vec<u8>)>,
+    nodes_file: &std::path::Path,
+    values_file: Option<&std::path::Path>,
+) -> Result<Hash> {
+    let leaves = sorted_leaves::<H>(mode, kvs);
+    if leaves.is_empty() {
+        return Ok(ZERO_HASH);
+    }
+    let mut internal = Vec::with_capacity(leaves.len() - 1);
+    let root = internal_nodes::<H>(leaves.iter().map(|leaf| (leaf.0, leaf.1)), |key, hash| {
+        internal.push((key, hash));
+        Ok(())
+    })?;
+    internal.par_sort_unstable_by_key(|node| node.0);
+
+    // SST keys must be strictly ascending, merge the leaves into the internal nodes
+    let mut writer = SstFileWriter::create(opts);
+    writer.open(nodes_file)?;
+    let mut internal = internal.into_iter().peekable();
+    for (path, hash, _) in &leaves {
+        let leaf = NodeKey { path: *path, len: 256 };
+        while let Some((key, node_hash)) = internal.next_if(|(key, _)| *key < leaf) {
+            writer.put(serialize_key(&key), node_hash)?;
         }
+        writer.put(serialize_key(&leaf), hash)?;
     }
+    writer.finish()?;
 
-    fn seek_next(&self, key: &NodeKey) -> Option<(NodeKey, Hash)> {
-        let k_bytes = serialize_key(key);
-        let mut iter = self.txn.raw_iterator_cf(self.cf);
-        iter.seek(k_bytes);
+    if let Some(values_file) = values_file {
+        let mut writer = SstFileWriter::create(opts);
+        writer.open(values_file)?;
+        for (path, _, (k, v)) in &leaves {
+            writer.put(path, serialize_value(k, v))?;
+        }
+        writer.finish()?;
+    }
+    Ok(root)
+}
 
-        if iter.valid() {
-            let found_k = deserialize_key(iter.key().unwrap());
-            if found_k == *key {
-                iter.next();
-            }
+// ============================================================================
+// ROCKSDB SNAPSHOT IMPORT
+// ============================================================================
+
+// Import progress, in a column family of the caller's choosing:
+// "snapshot_import" -> root (32) || done (1) || has last (1) || last path (32) || last hash (32)
+//                      || right edge entries: key (34) || hash (32)
+const IMPORT_STATE_KEY: &[u8] = b"snapshot_import";
+
+fn serialize_import_state(state: &ImportState) -> Vec<u8> {
+    let mut v = Vec::with_capacity(98 + state.edge.len() * 66);
+    v.extend_from_slice(&state.root);
+    v.push(state.done as u8);
+    match &state.last {
+        Some((path, hash)) => {
+            v.push(1);
+            v.extend_from_slice(path);
+            v.extend_from_slice(hash);
         }
+        None => v.push(0),
+    }
+    for (key, hash) in &state.edge {
+        v.extend_from_slice(&serialize_key(key));
+        v.extend_from_slice(hash);
+    }
+    v
+}
+
+fn deserialize_import_state(data: &[u8]) -> Result<ImportState> {
+    let corrupt = || HubtError::CorruptNode { key: IMPORT_STATE_KEY.to_vec(), len: data.len() };
+    if data.len() < 34 || data[32] > 1 {
+        return Err(corrupt());
+    }
+    let root = data[0..32].try_into().unwrap();
+    let (last, rest) = match data[33] {
+        0 => (None, &data[34..]),
+        1 if data.len() >= 98 => (Some((data[34..66].try_into().unwrap(), data[66..98].try_into().unwrap())), &data[98..]),
+        _ => return Err(corrupt()),
+    };
+    if rest.len() % 66 != 0 {
+        return Err(corrupt());
+    }
+    let edge = rest.chunks(66).map(|c| deserialize_entry(&c[..34], &c[34..])).collect::<Result<_>>()?;
+    Ok(ImportState { root, last, edge, done: data[32] == 1 })
+}
+
+/// Progress of a snapshot import saved by `save_import_state`, `None` before the first chunk.
+pub fn load_import_state<D>(txn: &Transaction<'_, D>, cf: &ColumnFamily) -> Result<Option<ImportState>> {
+    txn.get_cf(cf, IMPORT_STATE_KEY)?.map(|v| deserialize_import_state(&v)).transpose()
+}
+
+/// Saves import progress in `cf`, commit it in the transaction that imported the chunk to resume
+/// exactly after it. `cf` must not be the tree's own column family.
+pub fn save_import_state<D>(txn: &Transaction<'_, D>, cf: &ColumnFamily, state: &ImportState) -> Result<()> {
+    txn.put_cf(cf, IMPORT_STATE_KEY, serialize_import_state(state))?;
+    Ok(())
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
 
-        if iter.valid() {
-            let found_k = deserialize_key(iter.key().unwrap());
-            let found_v: Hash = iter.value().unwrap().try_into().unwrap();
-            Some((found_k, found_v))
-        } else {
-            None
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hubt_mem::{Hubt, ValueHubt, VersionedHubt};
+    use rocksdb::{Options, TransactionDBOptions};
+    use std::collections::BTreeSet;
+    use std::time::Instant;
+
+    /// Opens a fresh `TransactionDB` with `hubt`, `values` and `meta` column families under the temp dir.
+    fn with_db(name: &str, f: impl FnOnce(&Arc<TransactionDB>, &ColumnFamily)) {
+        let dir = std::env::temp_dir().join(format!("hubt_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        {
+            let mut opts = Options::default();
+            opts.create_if_missing(true);
+            opts.create_missing_column_families(true);
+            let db: TransactionDB = TransactionDB::open_cf(&opts, &TransactionDBOptions::default(), &dir, ["hubt", "values", "meta"]).unwrap();
+            let db = Arc::new(db);
+            f(&db, db.cf_handle("hubt").unwrap());
         }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_matches_mem_backend() {
+        with_db("parity", |db, cf| {
+            let txn = db.transaction();
+            let mut rocks = RocksHubt::new(&txn, cf);
+            let mut mem = Hubt::new();
+            let batches: Vec<Vec<Op>> = vec![
+                (0..300u32).map(|i| Op::Insert(i.to_be_bytes().to_vec(), b"a".to_vec())).collect(),
+                (300..320u32).map(|i| Op::Insert(i.to_be_bytes().to_vec(), b"b".to_vec())).collect(),
+                (0..300u32).step_by(7).map(|i| Op::Delete(i.to_be_bytes().to_vec())).collect(),
+                vec![Op::Insert(1u32.to_be_bytes().to_vec(), b"c".to_vec()), Op::Delete(9999u32.to_be_bytes().to_vec())],
+            ];
+            for batch in batches {
+                mem.batch_update(batch.clone()).unwrap();
+                rocks.batch_update(batch).unwrap();
+                assert_eq!(rocks.root().unwrap(), mem.root().unwrap());
+            }
+
+            for i in [1u32, 7, 8, 9999] {
+                let k = i.to_be_bytes().to_vec();
+                assert_eq!(rocks.prove(k.clone()).unwrap(), mem.prove(k).unwrap());
+            }
+            let proof = rocks.prove(1u32.to_be_bytes().to_vec()).unwrap();
+            assert_eq!(RocksHubt::verify(&proof, 1u32.to_be_bytes().to_vec(), b"c".to_vec()), VerifyStatus::Included);
+
+            let keys: Vec<Vec<u8>> = [1u32, 7, 8, 9999].iter().map(|i| i.to_be_bytes().to_vec()).collect();
+            assert_eq!(rocks.prove_many(&keys).unwrap(), mem.prove_many(&keys).unwrap());
+            assert_eq!(rocks.witness(&keys).unwrap(), mem.witness(&keys).unwrap());
+        });
     }
 
-    // --- PROOF ---
+    #[test]
+    fn test_corrupt_entries_are_errors() {
+        with_db("corrupt", |db, cf| {
+            let txn = db.transaction();
+            let mut hubt = RocksHubt::new(&txn, cf);
+            hubt.batch_update((0..10u32).map(|i| Op::Insert(i.to_be_bytes().to_vec(), b"v".to_vec())).collect()).unwrap();
+
+            // Truncated hash on the root node
+            let root_key = serialize_key(&NodeKey { path: [0u8; 32], len: 0 });
+            txn.put_cf(cf, &root_key, [1u8; 5]).unwrap();
+            assert_eq!(hubt.root(), Err(HubtError::CorruptNode { key: root_key.clone(), len: 5 }));
+            assert!(hubt.prove(3u32.to_be_bytes().to_vec()).is_err());
+            assert!(hubt.batch_update(vec![Op::Insert(b"x".to_vec(), b"v".to_vec())]).is_err());
+
+            // Key that is not path || len
+            txn.delete_cf(cf, &root_key).unwrap();
+            txn.put_cf(cf, [0u8; 35], [0u8; 32]).unwrap();
+            assert_eq!(hubt.root(), Err(HubtError::BadKeyLength(vec![0u8; 35])));
+        });
+    }
 
-    pub fn prove(&self, k: Vec<u8>, v: Vec<u8>) -> Option<Proof> {
-        let path = sha256(&k);
-        let leaf_val = concat_and_hash(&k, &v);
+    #[test]
+    fn test_check_after_interrupted_update() {
+        with_db("check", |db, cf| {
+            let txn = db.transaction();
+            let mut hubt = RocksHubt::new(&txn, cf);
+            hubt.batch_update((0..100u32).map(|i| Op::Insert(i.to_be_bytes().to_vec(), b"v".to_vec())).collect()).unwrap();
+            assert!(hubt.check().unwrap().is_ok());
+            let root = hubt.root().unwrap();
+
+            // A leaf and its split points written without the rehash
+            let path = hubt.mode().path::<Sha256Hasher>(b"late");
+            let mut dirty = BTreeSet::new();
+            hubt.store.put(NodeKey { path, len: 256 }, Sha256Hasher::concat_and_hash(b"late", b"v")).unwrap();
+            hubt.ensure_split_points(path, &mut dirty).unwrap();
+            let report = hubt.check().unwrap();
+            assert_eq!(report.root, root);
+            assert!(!dirty.is_empty() && dirty.iter().all(|key| report.problems.contains(&Problem::Placeholder(*key))));
+
+            hubt.repair().unwrap();
+            let mut expected = Hubt::new();
+            expected.batch_update((0..100u32).map(|i| Op::Insert(i.to_be_bytes().to_vec(), b"v".to_vec())).collect()).unwrap();
+            expected.batch_update(vec![Op::Insert(b"late".to_vec(), b"v".to_vec())]).unwrap();
+            assert!(hubt.check().unwrap().is_ok());
+            assert_eq!(hubt.root().unwrap(), expected.root().unwrap());
+        });
+    }
 
-        let key = NodeKey { path, len: 256 };
-        if let Some(h) = self.txn.get_cf(self.cf, serialize_key(&key)).ok().flatten() {
-            if h == &leaf_val[..] {
-                return Some(Proof {
-                    root: self.root(),
-                    nodes: self.generate_proof_nodes(path, 256),
-                });
+    #[test]
+    fn test_versioned_matches_mem_backend() {
+        with_db("versioned", |db, cf| {
+            let mut mem = VersionedHubt::new();
+            let batches: Vec<Vec<Op>> = vec![
+                (0..200u32).map(|i| Op::Insert(i.to_be_bytes().to_vec(), b"a".to_vec())).collect(),
+                (100..250u32).map(|i| Op::Insert(i.to_be_bytes().to_vec(), b"b".to_vec())).collect(),
+                (0..250u32).step_by(3).map(|i| Op::Delete(i.to_be_bytes().to_vec())).collect(),
+            ];
+
+            let txn = db.transaction();
+            for batch in batches {
+                // Reopen per batch, the head version is read back from the column family
+                let mut rocks = VersionedRocksHubt::open(&txn, cf).unwrap();
+                assert_eq!(rocks.commit(batch.clone()).unwrap(), mem.commit(batch).unwrap());
             }
-        }
-        None
-    }
-
-    fn generate_proof_nodes(&self, path: Path, len: u16) -> Vec<ProofNode> {
-        let mut ancestors = Vec::new();
-        let mut cursor = NodeKey { path, len: 256 };
-
-        loop {
-            match self.seek_prev(&cursor) {
-                None => break,
-                Some((k, _)) => {
-                    let is_same = k == cursor;
-                    if prefix_match_be(&path, &k.path, k.len) {
-                        if k.len < len { ancestors.push(k); }
-                        if k.len > 0 { cursor = NodeKey{path: k.path, len: k.len-1}; } else { break; }
-                    } else {
-                         let (lcp_p, lcp_l) = lcp_be(&path, &k.path);
-                         let jump = NodeKey{ path: lcp_p, len: lcp_l + 1 };
-                         if jump < k { cursor = jump; }
-                         else if is_same {
-                             if k.len > 0 { cursor = NodeKey{path:k.path, len:k.len-1}; } else { break; }
-                         } else { cursor = k; }
-                    }
+
+            let rocks = VersionedRocksHubt::open(&txn, cf).unwrap();
+            assert_eq!(rocks.version(), 3);
+            for version in 0..=3 {
+                assert_eq!(rocks.root_at(version).unwrap(), mem.root_at(version).unwrap());
+                for i in [0u32, 1, 150, 999] {
+                    let k = i.to_be_bytes().to_vec();
+                    assert_eq!(rocks.prove_at(version, k.clone()).unwrap(), mem.prove_at(version, k).unwrap());
                 }
             }
-        }
-        ancestors.sort_unstable_by(|a, b| b.len.cmp(&a.len));
+            assert_eq!(rocks.root_at(4), Err(HubtError::UnknownVersion(4)));
+        });
+    }
 
-        let mut nodes = Vec::new();
-        for anc in ancestors {
-            let my_dir = get_bit_be(&path, anc.len);
-            let sibling_dir = 1 - my_dir;
+    #[test]
+    fn test_background_pruning() {
+        with_db("prune", |db, cf| {
+            let policy = PrunePolicy { keep_last: Some(4), checkpoint_every: Some(10), pinned: BTreeSet::from([7]) };
+            let pruner = BackgroundPruner::spawn(db.clone(), "hubt", policy.clone(), 16, Duration::from_millis(1));
+
+            // The writer keeps committing while the pruner sweeps behind it
+            let mut mem = VersionedHubt::new();
+            for v in 1..=40u32 {
+                let ops: Vec<Op> = (0..30u32).map(|i| Op::Insert(i.to_be_bytes().to_vec(), v.to_be_bytes().to_vec())).collect();
+                let txn = db.transaction();
+                let mut rocks = VersionedRocksHubt::open(&txn, cf).unwrap();
+                assert_eq!(rocks.commit(ops.clone()).unwrap(), mem.commit(ops).unwrap());
+                txn.commit().unwrap();
+            }
+            pruner.stop().unwrap();
+
+            let txn = db.transaction();
+            let mut rocks = VersionedRocksHubt::open(&txn, cf).unwrap();
+            while !rocks.prune(&policy, 64).unwrap().done {}
+
+            let retained = [0, 7, 10, 20, 30, 37, 38, 39, 40];
+            for version in 0..=40 {
+                if retained.contains(&version) {
+                    assert_eq!(rocks.root_at(version).unwrap(), mem.root_at(version).unwrap());
+                    let k = 3u32.to_be_bytes().to_vec();
+                    assert_eq!(rocks.prove_at(version, k.clone()).unwrap(), mem.prove_at(version, k).unwrap());
+                } else {
+                    assert_eq!(rocks.root_at(version), Err(HubtError::Pruned(version)));
+                }
+            }
+
+            // Only the leaves and branches of the retained versions are left
+            mem.prune(&policy, usize::MAX).unwrap();
+            let mut iter = txn.raw_iterator_cf(cf);
+            iter.seek([VERSIONED_NODE_PREFIX]);
+            let mut entries = 0;
+            while iter.key().is_some_and(|k| k[0] == VERSIONED_NODE_PREFIX) {
+                entries += 1;
+                iter.next();
+            }
+            assert_eq!(entries, mem.store.backend.entries.len());
+        });
+    }
 
-            let mut t_path = anc.path;
-            set_bit_be(&mut t_path, anc.len, sibling_dir);
-            mask_after_be(&mut t_path, anc.len + 1);
-            let t_key = NodeKey { path: t_path, len: anc.len + 1 };
+    #[test]
+    fn test_background_pruner_retries_conflicts() {
+        with_db("prune_conflict", |db, cf| {
+            for v in 1..=10u32 {
+                let txn = db.transaction();
+                let ops = (0..30u32).map(|i| Op::Insert(i.to_be_bytes().to_vec(), v.to_be_bytes().to_vec())).collect();
+                VersionedRocksHubt::open(&txn, cf).unwrap().commit(ops).unwrap();
+                txn.commit().unwrap();
+            }
 
-            let s_hash = self.seek_next(&t_key)
-                .filter(|(k, _)| prefix_match_be(&k.path, &t_path, anc.len + 1))
-                .map(|(_, h)| h).unwrap_or(ZERO_HASH);
+            // Hold the prune state's lock, every step times out on it until released
+            let blocker = db.transaction();
+            blocker.get_for_update_cf(cf, VERSIONED_PRUNE_KEY, true).unwrap();
+            let policy = PrunePolicy { keep_last: Some(2), checkpoint_every: None, pinned: BTreeSet::new() };
+            let pruner = BackgroundPruner::spawn(db.clone(), "hubt", policy, 16, Duration::from_millis(1));
+            let deadline = Instant::now() + Duration::from_secs(30);
+            while pruner.last_error().is_none() {
+                assert!(Instant::now() < deadline, "pruner never hit the lock");
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert!(matches!(pruner.last_error(), Some(HubtError::Busy(_))));
+            assert!(pruner.is_alive());
+
+            blocker.rollback().unwrap();
+            drop(blocker);
+            loop {
+                let txn = db.transaction();
+                if VersionedRocksHubt::open(&txn, cf).unwrap().root_at(1) == Err(HubtError::Pruned(1)) {
+                    break;
+                }
+                assert!(Instant::now() < deadline, "pruner did not resume");
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert!(pruner.is_alive());
+            pruner.stop().unwrap();
+        });
+    }
 
-            nodes.push(ProofNode { hash: s_hash, direction: sibling_dir, len: anc.len });
+    #[test]
+    fn test_values_column_family() {
+        with_db("values", |db, cf| {
+            let values_cf = db.cf_handle("values").unwrap();
+            let txn = db.transaction();
+            let mut rocks = ValueRocksHubt::new(&txn, cf, values_cf);
+            let mut mem = ValueHubt::new();
+            let batches: Vec<Vec<Op>> = vec![
+                (0..100u32).map(|i| Op::Insert(i.to_be_bytes().to_vec(), vec![i as u8; i as usize])).collect(),
+                vec![Op::Insert(5u32.to_be_bytes().to_vec(), b"five".to_vec()), Op::Delete(6u32.to_be_bytes().to_vec())],
+            ];
+            for batch in batches {
+                mem.batch_update(batch.clone()).unwrap();
+                rocks.batch_update(batch).unwrap();
+                assert_eq!(rocks.root().unwrap(), mem.root().unwrap());
+            }
+
+            for i in [0u32, 5, 6, 42, 500] {
+                let k = i.to_be_bytes().to_vec();
+                assert_eq!(rocks.get(&k).unwrap(), mem.get(&k).unwrap());
+                assert_eq!(rocks.prove(k.clone()).unwrap(), mem.prove(k).unwrap());
+            }
+            assert_eq!(rocks.get(&5u32.to_be_bytes()).unwrap(), Some(b"five".to_vec()));
+            let leaves: Vec<Leaf> = rocks.leaves().collect::<Result<_>>().unwrap();
+            assert_eq!(leaves.len(), 99);
+            assert_eq!(leaves, mem.leaves().collect::<Result<Vec<_>>>().unwrap());
+            let prefix = leaves[40].path;
+            assert_eq!(rocks.prove_subtree(prefix, 5).unwrap(), mem.prove_subtree(prefix, 5).unwrap());
+            assert_eq!(rocks.prove(42u32.to_be_bytes().to_vec()).unwrap().value, Some(vec![42u8; 42]));
+
+            let path = Sha256Hasher::hash(&7u32.to_be_bytes());
+            txn.put_cf(values_cf, path, [0, 0, 0, 9, 1]).unwrap();
+            assert_eq!(rocks.get(&7u32.to_be_bytes()), Err(HubtError::CorruptValue(path.to_vec())));
+        });
+    }
+
+    #[test]
+    fn test_sst_bulk_load() {
+        let dir = std::env::temp_dir().join(format!("hubt_sst_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+
+        let kvs: Vec<(Vec<u8>, Vec<u8>)> = (0..2000u32).map(|i| (i.to_be_bytes().to_vec(), vec![i as u8; 3])).collect();
+        let (nodes_file, values_file) = (dir.join("nodes.sst"), dir.join("values.sst"));
+        let root = write_sst::<Sha256Hasher>(&opts, KeyMode::Hashed, kvs.clone(), &nodes_file, Some(&values_file)).unwrap();
+        assert_eq!(write_sst::<Sha256Hasher>(&opts, KeyMode::Hashed, vec![], &nodes_file, None).unwrap(), ZERO_HASH);
+        {
+            let db = DB::open_cf(&opts, dir.join("db"), ["hubt", "values"]).unwrap();
+            db.ingest_external_file_cf(db.cf_handle("hubt").unwrap(), vec![&nodes_file]).unwrap();
+            db.ingest_external_file_cf(db.cf_handle("values").unwrap(), vec![&values_file]).unwrap();
         }
-        nodes
+
+        let db: TransactionDB = TransactionDB::open_cf(&opts, &TransactionDBOptions::default(), dir.join("db"), ["hubt", "values"]).unwrap();
+        let txn = db.transaction();
+        let mut rocks = ValueRocksHubt::new(&txn, db.cf_handle("hubt").unwrap(), db.cf_handle("values").unwrap());
+        let mut mem = ValueHubt::new();
+        mem.batch_update(kvs.into_iter().map(|(k, v)| Op::Insert(k, v)).collect()).unwrap();
+        assert_eq!(root, mem.root().unwrap());
+        assert_eq!(rocks.root().unwrap(), root);
+        for i in [0u32, 7, 1999, 5000] {
+            let k = i.to_be_bytes().to_vec();
+            assert_eq!(rocks.get(&k).unwrap(), mem.get(&k).unwrap());
+            assert_eq!(rocks.prove(k.clone()).unwrap(), mem.prove(k).unwrap());
+        }
+
+        // The ingested tree keeps working incrementally
+        let batch = vec![Op::Delete(7u32.to_be_bytes().to_vec()), Op::Insert(b"new".to_vec(), b"v".to_vec())];
+        rocks.batch_update(batch.clone()).unwrap();
+        mem.batch_update(batch).unwrap();
+        assert_eq!(rocks.root().unwrap(), mem.root().unwrap());
+        drop(txn);
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_snapshot_sync_between_databases() {
+        with_db("snapshot_source", |source_db, source_cf| {
+            let source_txn = source_db.transaction();
+            let mut source = ValueRocksHubt::new(&source_txn, source_cf, source_db.cf_handle("values").unwrap());
+            source.batch_update((0..1000u32).map(|i| Op::Insert(i.to_be_bytes().to_vec(), vec![i as u8; 4])).collect()).unwrap();
+            let root = source.root().unwrap();
+
+            with_db("snapshot_dest", |db, cf| {
+                let (values_cf, meta_cf) = (db.cf_handle("values").unwrap(), db.cf_handle("meta").unwrap());
+                let mut chunks = 0;
+                loop {
+                    let txn = db.transaction();
+                    let mut state = load_import_state(&txn, meta_cf).unwrap().unwrap_or_else(|| ImportState::new(root));
+                    if state.done {
+                        break;
+                    }
+                    let chunk = source.export_chunk(state.after(), 64).unwrap();
+                    let mut dest = ValueRocksHubt::new(&txn, cf, values_cf);
+
+                    let mut forged = chunk.clone();
+                    forged.leaves[0].kv = Some((b"forged".to_vec(), vec![0; 4]));
+                    assert_eq!(dest.import_chunk(&mut state.clone(), &forged), Err(HubtError::InvalidChunk));
+
+                    dest.import_chunk(&mut state, &chunk).unwrap();
+                    save_import_state(&txn, meta_cf, &state).unwrap();
+                    chunks += 1;
+                    // Every third chunk is interrupted before the commit and fetched again
+                    if chunks % 3 != 0 {
+                        txn.commit().unwrap();
+                    }
+                }
+                // 16 chunks of 64 leaves, 7 of them fetched again
+                assert_eq!(chunks, 23);
+
+                let txn = db.transaction();
+                let dest = ValueRocksHubt::new(&txn, cf, values_cf);
+                assert_eq!(dest.root().unwrap(), root);
+                assert_eq!(dest.leaves().collect::<Result<Vec<_>>>().unwrap(), source.leaves().collect::<Result<Vec<_>>>().unwrap());
+                for i in [0u32, 500, 999, 1000] {
+                    let k = i.to_be_bytes().to_vec();
+                    assert_eq!(dest.prove(k.clone()).unwrap(), source.prove(k).unwrap());
+                }
+                let state = load_import_state(&txn, meta_cf).unwrap().unwrap();
+                assert_eq!(deserialize_import_state(&serialize_import_state(&state)).unwrap(), state);
+            });
+        });
     }
 }
@@ -1,2 +1,24 @@
+pub mod bits;
+pub mod bulk;
+pub mod changes;
+pub mod check;
+pub mod diff;
+pub mod error;
+pub mod hasher;
 pub mod hubt_mem;
 pub mod hubt_rocksdb;
+pub mod multiproof;
+pub mod proof;
+pub mod scan;
+pub mod snapshot;
+pub mod store;
+pub mod subtree;
+pub mod tree;
+pub mod undo;
+pub mod values;
+pub mod versioned;
+pub mod wire;
+pub mod witness;
+
+#[cfg(test)]
+mod test_util;
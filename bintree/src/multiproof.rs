@@ -0,0 +1,427 @@
+use crate::bits::{get_bit_be, mask_after_be, prefix_match_be, set_bit_be, Path};
+use crate::error::Result;
+use crate::hasher::{Hash, KeyMode, TreeHasher, ZERO_HASH};
+use crate::proof::{Proof, ProofNode, VerifyStatus};
+use crate::store::{HubtRead, NodeKey};
+use crate::tree::{bound, Tree};
+use std::collections::BTreeSet;
+
+/// One step of the pre-order walk in `MultiProof::shape`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultiStep {
+    /// Internal node of this length, followed by its left then right subtree.
+    Branch(u16),
+    /// Next entry of `found`.
+    Found,
+    /// Next entry of `siblings`.
+    Sibling,
+}
+
+/// A node some key's proof ends on, see `Proof::path` / `Proof::hash` / `Proof::len`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoundNode {
+    pub path: Path,
+    pub hash: Hash,
+    pub len: u16,
+}
+
+/// Proof for many keys against one root.
+///
+/// `shape` walks the part of the tree shared by the keys' paths, from the root down.
+/// Branches are recomputed by the verifier, so every sibling hash is listed once.
+/// Siblings are bound to their position (`TreeHasher::bind`), branches and found nodes are
+/// bound by the verifier to the lengths in `shape` and the paths in `found`.
+/// Each key is then checked as the single `Proof` its path selects in that walk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultiProof {
+    pub root: Hash,
+    pub shape: Vec<MultiStep>,
+    pub found: Vec<FoundNode>,
+    pub siblings: Vec<Hash>,
+}
+
+enum Part {
+    Branch { path: Path, len: u16, hash: Hash, children: [usize; 2] },
+    Found(usize),
+    Sibling(Hash),
+}
+
+/// The subtree described by `shape`, with every branch hash recomputed.
+struct Pruned<'a> {
+    proof: &'a MultiProof,
+    parts: Vec<Part>,
+    step: usize,
+    found: usize,
+    siblings: usize,
+}
+
+impl<'a> Pruned<'a> {
+    /// Parses and hashes the whole shape, `None` if it is malformed or not rooted at `proof.root`.
+    fn build<H: TreeHasher>(proof: &'a MultiProof) -> Option<Self> {
+        let mut pruned = Pruned { proof, parts: Vec::new(), step: 0, found: 0, siblings: 0 };
+        let top = pruned.parse::<H>(0)?;
+        let complete = pruned.step == proof.shape.len()
+            && pruned.found == proof.found.len()
+            && pruned.siblings == proof.siblings.len();
+        (complete && pruned.bound::<H>(top) == proof.root).then_some(pruned)
+    }
+
+    /// Parses the subtree at the current step, branches below must be at least `min_len` long.
+    fn parse<H: TreeHasher>(&mut self, min_len: u16) -> Option<usize> {
+        let step = *self.proof.shape.get(self.step)?;
+        self.step += 1;
+        let part = match step {
+            MultiStep::Found => {
+                let node = self.proof.found.get(self.found)?;
+                let mut canonical = node.path;
+                mask_after_be(&mut canonical, node.len);
+                if node.len < min_len || node.len > 256 || canonical != node.path {
+                    return None;
+                }
+                self.found += 1;
+                Part::Found(self.found - 1)
+            }
+            MultiStep::Sibling => {
+                let hash = *self.proof.siblings.get(self.siblings)?;
+                self.siblings += 1;
+                Part::Sibling(hash)
+            }
+            MultiStep::Branch(len) => {
+                if len < min_len || len >= 256 {
+                    return None;
+                }
+                let children = [self.parse::<H>(len + 1)?, self.parse::<H>(len + 1)?];
+
+                // Children must agree on the branch's prefix and sit on their own side of it
+                let mut path = None;
+                for (dir, &child) in children.iter().enumerate() {
+                    if let Some((child_path, _)) = self.position(child) {
+                        if get_bit_be(&child_path, len) as usize != dir {
+                            return None;
+                        }
+                        let mut prefix = child_path;
+                        mask_after_be(&mut prefix, len);
+                        if path.is_some_and(|p| p != prefix) {
+                            return None;
+                        }
+                        path = Some(prefix);
+                    }
+                }
+                // A branch with no found node below would have been a sibling
+                let path = path?;
+                let hash = H::node(&self.bound::<H>(children[0]), &self.bound::<H>(children[1]));
+                Part::Branch { path, len, hash, children }
+            }
+        };
+        self.parts.push(part);
+        Some(self.parts.len() - 1)
+    }
+
+    fn hash(&self, at: usize) -> Hash {
+        match self.parts[at] {
+            Part::Branch { hash, .. } => hash,
+            Part::Found(i) => self.proof.found[i].hash,
+            Part::Sibling(hash) => hash,
+        }
+    }
+
+    /// Hash of `at` bound to its position, siblings come bound.
+    fn bound<H: TreeHasher>(&self, at: usize) -> Hash {
+        match self.position(at) {
+            Some((path, len)) => H::bind(len, &path, &self.hash(at)),
+            None => self.hash(at),
+        }
+    }
+
+    fn position(&self, at: usize) -> Option<(Path, u16)> {
+        match self.parts[at] {
+            Part::Branch { path, len, .. } => Some((path, len)),
+            Part::Found(i) => Some((self.proof.found[i].path, self.proof.found[i].len)),
+            Part::Sibling(_) => None,
+        }
+    }
+
+    /// The single proof for `target`: follow its bits until a found node or a branch it leaves.
+    /// `None` if the walk runs into a sibling, i.e. the multiproof does not cover `target`.
+    fn proof_for<H: TreeHasher>(&self, target: &Path) -> Option<Proof> {
+        let mut nodes = Vec::new();
+        // The root is parsed last
+        let mut at = self.parts.len() - 1;
+        while let Part::Branch { path, len, children, .. } = self.parts[at] {
+            if !prefix_match_be(target, &path, len) {
+                break;
+            }
+            let dir = get_bit_be(target, len);
+            let sibling_dir = 1 - dir;
+            nodes.push(ProofNode { hash: self.bound::<H>(children[sibling_dir as usize]), direction: sibling_dir, len });
+            at = children[dir as usize];
+        }
+        nodes.reverse();
+        let (path, len) = self.position(at)?;
+        Some(Proof { root: self.proof.root, nodes, path, hash: self.hash(at), len, value: None })
+    }
+
+    /// Adds the nodes under `at`, flagged if they stand for a subtree that is not revealed.
+    /// Siblings come with a bound hash only, `key` is the shortest key on their side of the parent.
+    /// Flagged nodes hold their bound hash, see `tree::bound`.
+    fn reveal<H: TreeHasher>(&self, at: usize, key: NodeKey, out: &mut Vec<(NodeKey, Hash, bool)>) {
+        match self.parts[at] {
+            Part::Branch { path, len, hash, children } => {
+                out.push((NodeKey { path, len }, hash, false));
+                for (dir, &child) in children.iter().enumerate() {
+                    let mut side = path;
+                    set_bit_be(&mut side, len, dir as u8);
+                    self.reveal::<H>(child, NodeKey { path: side, len: len + 1 }, out);
+                }
+            }
+            Part::Found(i) => {
+                let node = &self.proof.found[i];
+                let key = NodeKey { path: node.path, len: node.len };
+                if node.len < 256 {
+                    out.push((key, self.bound::<H>(at), true));
+                } else {
+                    out.push((key, node.hash, false));
+                }
+            }
+            Part::Sibling(hash) if hash != ZERO_HASH => out.push((key, hash, true)),
+            Part::Sibling(_) => {}
+        }
+    }
+}
+
+impl MultiProof {
+    /// True if the proof is well formed and hashes up to `root`, whatever keys it is checked for.
+    pub(crate) fn is_consistent<H: TreeHasher>(&self) -> bool {
+        if self.root == ZERO_HASH {
+            return self.shape.is_empty() && self.found.is_empty() && self.siblings.is_empty();
+        }
+        Pruned::build::<H>(self).is_some()
+    }
+
+    /// Nodes of a consistent proof with their opaque flag, see `WitnessStore`.
+    pub(crate) fn revealed<H: TreeHasher>(&self) -> Option<Vec<(NodeKey, Hash, bool)>> {
+        let top = NodeKey { path: [0u8; 32], len: 0 };
+        if self.root == ZERO_HASH {
+            return self.is_consistent::<H>().then(Vec::new);
+        }
+        if self.shape.is_empty() && self.found.is_empty() && self.siblings.is_empty() {
+            return Some(vec![(top, self.root, true)]);
+        }
+        let pruned = Pruned::build::<H>(self)?;
+        let mut nodes = Vec::new();
+        pruned.reveal::<H>(pruned.parts.len() - 1, top, &mut nodes);
+        Some(nodes)
+    }
+
+    /// Verifies every `(k, v)` against the one root, see `Proof::verify` for the statuses.
+    ///
+    /// A malformed multiproof marks every key `Invalid`, a key it does not cover is `Invalid` alone.
+    pub fn verify<H: TreeHasher>(&self, mode: KeyMode, kvs: &[(Vec<u8>, Vec<u8>)]) -> Vec<VerifyStatus> {
+        if self.root == ZERO_HASH {
+            let empty = self.shape.is_empty() && self.found.is_empty() && self.siblings.is_empty();
+            let status = || if empty { VerifyStatus::NonExistence } else { VerifyStatus::Invalid };
+            return kvs.iter().map(|_| status()).collect();
+        }
+        let pruned = match Pruned::build::<H>(self) {
+            Some(pruned) => pruned,
+            None => return kvs.iter().map(|_| VerifyStatus::Invalid).collect(),
+        };
+        kvs.iter()
+            .map(|(k, v)| match pruned.proof_for::<H>(&mode.path::<H>(k)) {
+                Some(proof) => proof.verify::<H>(mode, k, v),
+                None => VerifyStatus::Invalid,
+            })
+            .collect()
+    }
+}
+
+impl<S: HubtRead, H: TreeHasher> Tree<S, H> {
+    /// One proof for all `keys`, sharing the siblings their single proofs have in common.
+    pub fn prove_many(&self, keys: &[Vec<u8>]) -> Result<MultiProof> {
+        let paths: Vec<Path> = keys.iter().map(|k| self.mode().path::<H>(k)).collect();
+        self.prove_paths(&paths)
+    }
+
+    /// Same as `prove_many` for key paths.
+    pub(crate) fn prove_paths(&self, paths: &[Path]) -> Result<MultiProof> {
+        let mut proof = MultiProof { root: ZERO_HASH, shape: Vec::new(), found: Vec::new(), siblings: Vec::new() };
+        let (top, top_hash) = match self.store.seek_next(&NodeKey { path: [0u8; 32], len: 0 })? {
+            Some(top) => top,
+            None => return Ok(proof),
+        };
+        proof.root = bound::<S, H>(&self.store, &top, top_hash);
+
+        // Same found node as `prove`, the branches on the way are expanded instead of listed
+        let mut found = BTreeSet::new();
+        let mut branches = BTreeSet::new();
+        for path in paths {
+            if let Some((key, _)) = self.find_longest_prefix_node(path)? {
+                branches.extend(self.ancestors(key.path, key.len)?);
+                found.insert(key);
+            }
+        }
+        if !found.is_empty() {
+            self.walk_multi(top, top_hash, &found, &branches, &mut proof)?;
+        }
+        Ok(proof)
+    }
+
+    fn walk_multi(
+        &self,
+        key: NodeKey,
+        hash: Hash,
+        found: &BTreeSet<NodeKey>,
+        branches: &BTreeSet<NodeKey>,
+        proof: &mut MultiProof,
+    ) -> Result<()> {
+        if branches.contains(&key) {
+            proof.shape.push(MultiStep::Branch(key.len));
+            for dir in 0..2 {
+                match self.get_child(key.path, key.len, dir)? {
+                    Some((child, child_hash)) => self.walk_multi(child, child_hash, found, branches, proof)?,
+                    None => {
+                        proof.shape.push(MultiStep::Sibling);
+                        proof.siblings.push(ZERO_HASH);
+                    }
+                }
+            }
+        } else if found.contains(&key) {
+            proof.shape.push(MultiStep::Found);
+            proof.found.push(FoundNode { path: key.path, hash, len: key.len });
+        } else {
+            proof.shape.push(MultiStep::Sibling);
+            proof.siblings.push(bound::<S, H>(&self.store, &key, hash));
+        }
+        Ok(())
+    }
+
+    /// Same as `Hubt::verify_many`, for multiproofs generated by a tree using `mode` and this tree's hasher.
+    pub fn verify_many_with_mode(mode: KeyMode, proof: &MultiProof, kvs: &[(Vec<u8>, Vec<u8>)]) -> Vec<VerifyStatus> {
+        proof.verify::<H>(mode, kvs)
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::lcp_be;
+    use crate::hasher::{Hardened, Sha256Hasher};
+    use crate::hubt_mem::Hubt;
+    use crate::test_util::key;
+    use crate::tree::Op;
+
+    #[test]
+    fn test_multiproof_matches_single_proofs() {
+        let mut hubt = Hubt::new();
+        hubt.batch_update((0..5000).map(|i| Op::Insert(key(i), b"v".to_vec())).collect()).unwrap();
+
+        // Present keys, a changed value, missing keys and a duplicate
+        let mut kvs: Vec<(Vec<u8>, Vec<u8>)> = (0..5000).step_by(7).map(|i| (key(i), b"v".to_vec())).collect();
+        kvs.push((key(3), b"changed".to_vec()));
+        kvs.extend((10_000..10_050).map(|i| (key(i), Vec::new())));
+        kvs.push((key(14), b"v".to_vec()));
+        let keys: Vec<Vec<u8>> = kvs.iter().map(|(k, _)| k.clone()).collect();
+
+        let multi = hubt.prove_many(&keys).unwrap();
+        assert_eq!(multi.root, hubt.root().unwrap());
+        let statuses = Hubt::verify_many(&multi, &kvs);
+        for ((k, v), status) in kvs.iter().zip(&statuses) {
+            let single = hubt.prove(k.clone()).unwrap();
+            assert_eq!(*status, Hubt::verify(&single, k.clone(), v.clone()));
+        }
+        assert_eq!(statuses[kvs.len() - 52], VerifyStatus::Mismatch);
+        assert!(statuses[..kvs.len() - 52].iter().all(|s| *s == VerifyStatus::Included));
+        assert!(statuses[kvs.len() - 51..kvs.len() - 1].iter().all(|s| *s == VerifyStatus::NonExistence));
+
+        // Upper siblings are shared instead of repeated per key
+        let single_nodes: usize = keys.iter().map(|k| hubt.prove(k.clone()).unwrap().nodes.len()).sum();
+        assert!(multi.siblings.len() * 4 < single_nodes);
+
+        // Keys outside the multiproof are not covered by it
+        let partial = hubt.prove_many(&keys[..10]).unwrap();
+        assert_eq!(Hubt::verify_many(&partial, &kvs[..10]), statuses[..10]);
+        assert_eq!(Hubt::verify_many(&partial, &[(key(4000), b"v".to_vec())]), vec![VerifyStatus::Invalid]);
+    }
+
+    #[test]
+    fn test_multiproof_edge_cases_and_tampering() {
+        let kvs = vec![(key(1), b"v".to_vec()), (key(2), b"v".to_vec())];
+        let keys = vec![key(1), key(2)];
+
+        let empty = Hubt::new().prove_many(&keys).unwrap();
+        assert_eq!(Hubt::verify_many(&empty, &kvs), vec![VerifyStatus::NonExistence, VerifyStatus::NonExistence]);
+
+        let mut single = Hubt::new();
+        single.batch_update(vec![Op::Insert(key(1), b"v".to_vec())]).unwrap();
+        let multi = single.prove_many(&keys).unwrap();
+        assert_eq!(multi.shape, vec![MultiStep::Found]);
+        assert_eq!(Hubt::verify_many(&multi, &kvs), vec![VerifyStatus::Included, VerifyStatus::NonExistence]);
+
+        let mut hubt = Hubt::new();
+        hubt.batch_update((0..64).map(|i| Op::Insert(key(i), b"v".to_vec())).collect()).unwrap();
+        let multi = hubt.prove_many(&keys).unwrap();
+        assert_eq!(Hubt::verify_many(&multi, &kvs), vec![VerifyStatus::Included, VerifyStatus::Included]);
+
+        let mut forged = multi.clone();
+        forged.siblings[0][0] ^= 1;
+        assert_eq!(Hubt::verify_many(&forged, &kvs), [VerifyStatus::Invalid, VerifyStatus::Invalid]);
+
+        let mut forged = multi.clone();
+        forged.siblings.push(ZERO_HASH);
+        assert_eq!(Hubt::verify_many(&forged, &kvs), [VerifyStatus::Invalid, VerifyStatus::Invalid]);
+
+        let mut forged = multi.clone();
+        forged.shape.pop();
+        assert_eq!(Hubt::verify_many(&forged, &kvs), [VerifyStatus::Invalid, VerifyStatus::Invalid]);
+
+        // Hiding a key's leaf behind a sibling does not turn it into an exclusion
+        let mut forged = multi;
+        let at = forged.shape.iter().position(|s| *s == MultiStep::Found).unwrap();
+        let before = forged.shape[..at].iter().filter(|s| **s == MultiStep::Sibling).count();
+        forged.shape[at] = MultiStep::Sibling;
+        let node = forged.found.remove(0);
+        forged.siblings.insert(before, node.hash);
+        assert!(Hubt::verify_many(&forged, &kvs).iter().all(|s| *s != VerifyStatus::NonExistence));
+    }
+
+    #[test]
+    fn test_multiproof_positions_are_committed() {
+        type H = Hardened<Sha256Hasher>;
+        // A second key whose split from key 1 has a bit on key 1's side above it
+        let path = |i| KeyMode::Hashed.path::<Sha256Hasher>(&key(i));
+        let other = (2..)
+            .find(|&i| {
+                let (_, div) = lcp_be(&path(1), &path(i));
+                (0..div).any(|b| get_bit_be(&path(1), b) == get_bit_be(&path(1), div))
+            })
+            .unwrap();
+        let mut hubt = Hubt::<H>::with_mode(KeyMode::Hashed);
+        hubt.batch_update(vec![Op::Insert(key(1), b"v".to_vec()), Op::Insert(key(other), b"v".to_vec())]).unwrap();
+        let multi = hubt.prove_many(&[key(1)]).unwrap();
+        let dir = multi.shape.iter().position(|s| *s == MultiStep::Found).unwrap() as u8 - 1;
+        let present = [(key(other), b"v".to_vec())];
+        assert_eq!(multi.verify::<H>(KeyMode::Hashed, &present), vec![VerifyStatus::Invalid]);
+
+        // Key 1's leaf moved next to the other key and the branch moved to a bit that keeps it on its side
+        let mut moved = path(other);
+        let last = get_bit_be(&moved, 255);
+        set_bit_be(&mut moved, 255, 1 - last);
+        let len = (0..255).find(|&i| get_bit_be(&moved, i) == dir).unwrap();
+        let mut forged = multi.clone();
+        forged.shape[0] = MultiStep::Branch(len);
+        forged.found[0].path = moved;
+        assert_eq!(forged.verify::<H>(KeyMode::Hashed, &present), vec![VerifyStatus::Invalid]);
+
+        // Or left in place with only the branch moved above the point where the keys split
+        let mut forged = multi;
+        let leaf = forged.found[0].path;
+        let (_, div) = lcp_be(&leaf, &path(other));
+        let len = (0..div).find(|&i| get_bit_be(&leaf, i) == dir).unwrap();
+        forged.shape[0] = MultiStep::Branch(len);
+        assert_eq!(forged.verify::<H>(KeyMode::Hashed, &present), vec![VerifyStatus::Invalid]);
+    }
+}
@@ -0,0 +1,2 @@
+pub(crate) use hubt_verifier::proof::verify_structure;
+pub use hubt_verifier::proof::{Proof, ProofNode, VerifyStatus};
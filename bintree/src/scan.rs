@@ -0,0 +1,164 @@
+use crate::bits::{mask_after_be, prefix_match_be, Path};
+use crate::error::Result;
+use crate::hasher::{Hash, TreeHasher};
+use crate::store::{HubtRead, NodeKey};
+use crate::tree::Tree;
+use std::cmp::min;
+
+/// A leaf as yielded by `Leaves`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leaf {
+    pub path: Path,
+    pub hash: Hash,
+    /// Original key and value, for stores that keep values.
+    pub kv: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+/// Leaves under a bit prefix in path order, see `Tree::leaves`.
+///
+/// Pages are resumed with `after(last.path)`, nothing is held between pages.
+pub struct Leaves<'a, S> {
+    store: &'a S,
+    prefix: Path,
+    prefix_len: u16,
+    next: Option<NodeKey>,
+}
+
+impl<'a, S: HubtRead> Leaves<'a, S> {
+    fn new(store: &'a S, mut prefix: Path, prefix_len: u16) -> Self {
+        let prefix_len = min(prefix_len, 256);
+        mask_after_be(&mut prefix, prefix_len);
+        Self { store, prefix, prefix_len, next: Some(NodeKey { path: prefix, len: 0 }) }
+    }
+
+    /// Continues the scan strictly after `path`, e.g. the last leaf of the previous page.
+    pub fn after(mut self, path: Path) -> Self {
+        let start = NodeKey { path, len: 257 };
+        if self.next.is_some_and(|next| next < start) {
+            self.next = Some(start);
+        }
+        self
+    }
+
+    fn next_leaf(&mut self, mut cursor: NodeKey) -> Result<Option<Leaf>> {
+        loop {
+            let (key, hash) = match self.store.seek_next(&cursor)? {
+                Some(found) => found,
+                None => return Ok(None),
+            };
+            if !prefix_match_be(&key.path, &self.prefix, self.prefix_len) {
+                return Ok(None);
+            }
+            // Skip internal nodes, nothing sorts between (path, len) and (path, len + 1)
+            cursor = NodeKey { path: key.path, len: key.len + 1 };
+            if key.len == 256 {
+                self.next = Some(cursor);
+                let kv = self.store.get_value(&key.path)?;
+                return Ok(Some(Leaf { path: key.path, hash, kv }));
+            }
+        }
+    }
+}
+
+impl<'a, S: HubtRead> Iterator for Leaves<'a, S> {
+    type Item = Result<Leaf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cursor = self.next.take()?;
+        self.next_leaf(cursor).transpose()
+    }
+}
+
+impl<S: HubtRead, H: TreeHasher> Tree<S, H> {
+    /// Every leaf in path order.
+    pub fn leaves(&self) -> Leaves<'_, S> {
+        Leaves::new(&self.store, [0u8; 32], 0)
+    }
+
+    /// Leaves whose path starts with the first `prefix_len` bits of `prefix`.
+    pub fn leaves_under(&self, prefix: Path, prefix_len: u16) -> Leaves<'_, S> {
+        Leaves::new(&self.store, prefix, prefix_len)
+    }
+
+    /// Leaves of the keys starting with `ns` (up to 8 bytes), for `KeyMode::Namespaced` trees.
+    pub fn namespace_leaves(&self, ns: &[u8]) -> Leaves<'_, S> {
+        let ns_len = min(ns.len(), 8);
+        let mut prefix = [0u8; 32];
+        prefix[..ns_len].copy_from_slice(&ns[..ns_len]);
+        Leaves::new(&self.store, prefix, ns_len as u16 * 8)
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::get_bit_be;
+    use crate::hasher::{KeyMode, Sha256Hasher};
+    use crate::hubt_mem::{Hubt, ValueHubt};
+    use crate::tree::Op;
+
+    fn collect(leaves: impl Iterator<Item = Result<Leaf>>) -> Vec<Leaf> {
+        leaves.collect::<Result<Vec<_>>>().unwrap()
+    }
+
+    #[test]
+    fn test_ordered_and_prefix_scans() {
+        let mut hubt = Hubt::new();
+        hubt.batch_update((0..300u32).map(|i| Op::Insert(i.to_be_bytes().to_vec(), b"v".to_vec())).collect()).unwrap();
+
+        let all = collect(hubt.leaves());
+        assert_eq!(all.len(), 300);
+        assert!(all.windows(2).all(|w| w[0].path < w[1].path));
+        let k = 9u32.to_be_bytes();
+        let leaf = all.iter().find(|l| l.path == Sha256Hasher::hash(&k)).unwrap();
+        assert_eq!((leaf.hash, leaf.kv.clone()), (Sha256Hasher::concat_and_hash(&k, b"v"), None));
+
+        // Bit prefixes split the leaves exactly
+        let mut prefix = [0u8; 32];
+        prefix[0] = 0b1010_0000;
+        let under = collect(hubt.leaves_under(prefix, 3));
+        let expected: Vec<&Leaf> = all.iter().filter(|l| (0..3).all(|i| get_bit_be(&l.path, i) == get_bit_be(&prefix, i))).collect();
+        assert!(!under.is_empty());
+        assert_eq!(under.iter().collect::<Vec<_>>(), expected);
+        assert_eq!(collect(hubt.leaves_under(all[17].path, 256)), vec![all[17].clone()]);
+
+        // Paging with `after` yields the same sequence
+        let mut paged = Vec::new();
+        let mut page = collect(hubt.leaves().take(64));
+        while let Some(last) = page.last().map(|l| l.path) {
+            paged.append(&mut page);
+            page = collect(hubt.leaves().after(last).take(64));
+        }
+        assert_eq!(paged, all);
+        assert!(collect(Hubt::new().leaves()).is_empty());
+    }
+
+    #[test]
+    fn test_namespace_scan_with_values() {
+        let mut hubt: ValueHubt = ValueHubt::with_mode(KeyMode::Namespaced);
+        let mut ops = Vec::new();
+        for ns in [b"accounts", b"contract", b"tx______"] {
+            for i in 0..40u32 {
+                ops.push(Op::Insert([&ns[..], &i.to_be_bytes()].concat(), i.to_be_bytes().to_vec()));
+            }
+        }
+        hubt.batch_update(ops).unwrap();
+
+        let leaves = collect(hubt.namespace_leaves(b"contract"));
+        assert_eq!(leaves.len(), 40);
+        for leaf in &leaves {
+            let (k, v) = leaf.kv.clone().unwrap();
+            assert_eq!(&k[..8], b"contract");
+            assert_eq!(k[8..], v[..]);
+        }
+        assert_eq!(collect(hubt.namespace_leaves(b"con")), leaves);
+        assert!(collect(hubt.namespace_leaves(b"missing!")).is_empty());
+
+        let rest = collect(hubt.namespace_leaves(b"contract").after(leaves[9].path));
+        assert_eq!(rest, leaves[10..]);
+    }
+}
@@ -0,0 +1,263 @@
+use crate::bits::Path;
+use crate::bulk::{finish_edge, push_leaf};
+use crate::error::{HubtError, Result};
+use crate::hasher::{Hash, KeyMode, TreeHasher, ZERO_HASH};
+use crate::multiproof::{FoundNode, MultiProof, MultiStep};
+use crate::scan::Leaf;
+use crate::store::{HubtRead, HubtStore, NodeKey};
+use crate::tree::Tree;
+use std::cmp::max;
+
+/// A run of consecutive leaves of a snapshot, see `Tree::export_chunk`.
+///
+/// `proof` covers the leaves and the last leaf of the previous chunk. The importer checks that no
+/// sibling sits between them, so no leaf was skipped. No sibling before the first leaf starts the
+/// snapshot, none after the last ends it. Leaves carry their k/v unless the tree binds positions
+/// (`Hardened`), plain trees without values can not be imported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotChunk {
+    pub leaves: Vec<Leaf>,
+    pub proof: MultiProof,
+}
+
+/// Progress of an import, see `Tree::import_chunk`. Persist it with the imported nodes to resume.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportState {
+    pub root: Hash,
+    /// Last imported leaf.
+    pub last: Option<(Path, Hash)>,
+    /// Finished subtrees on the right edge of the imported leaves, not written yet.
+    pub edge: Vec<(NodeKey, Hash)>,
+    pub done: bool,
+}
+
+impl ImportState {
+    pub fn new(root: Hash) -> Self {
+        Self { root, last: None, edge: Vec::new(), done: false }
+    }
+
+    /// Where the next chunk starts, for `Tree::export_chunk`.
+    pub fn after(&self) -> Option<Path> {
+        self.last.map(|(path, _)| path)
+    }
+}
+
+/// Checks `chunk` against the import so far, `Some(true)` if it ends the snapshot.
+fn check_chunk<H: TreeHasher>(mode: KeyMode, state: &ImportState, chunk: &SnapshotChunk) -> Option<bool> {
+    let proof = &chunk.proof;
+    if state.done || proof.root != state.root || !proof.is_consistent::<H>() {
+        return None;
+    }
+    if proof.root == ZERO_HASH {
+        return chunk.leaves.is_empty().then_some(true);
+    }
+    // Without a value, only a binding hasher fixes a leaf's path, see `TreeHasher::bind`
+    for leaf in &chunk.leaves {
+        match &leaf.kv {
+            Some((k, v)) if mode.path::<H>(k) != leaf.path || H::leaf(k, v) != leaf.hash => return None,
+            None if !H::BINDS_POSITIONS => return None,
+            _ => {}
+        }
+    }
+    let expected = state.last.into_iter().chain(chunk.leaves.iter().map(|leaf| (leaf.path, leaf.hash)));
+    let expected: Vec<FoundNode> = expected.map(|(path, hash)| FoundNode { path, hash, len: 256 }).collect();
+    if expected.is_empty() || proof.found != expected {
+        return None;
+    }
+
+    // Found and sibling steps are in path order, the leaves must be adjacent
+    let ends: Vec<MultiStep> = proof.shape.iter().copied().filter(|step| !matches!(step, MultiStep::Branch(_))).collect();
+    let first = ends.iter().position(|step| *step == MultiStep::Found)?;
+    let last = ends.iter().rposition(|step| *step == MultiStep::Found)?;
+    if ends[first..=last].contains(&MultiStep::Sibling) || (state.last.is_none() && first != 0) {
+        return None;
+    }
+    Some(last == ends.len() - 1)
+}
+
+impl<S: HubtRead, H: TreeHasher> Tree<S, H> {
+    /// Up to `max_leaves` leaves after `after` (from the first leaf if `None`), proven against the current root.
+    pub fn export_chunk(&self, after: Option<Path>, max_leaves: usize) -> Result<SnapshotChunk> {
+        let leaves = match after {
+            Some(path) => self.leaves().after(path),
+            None => self.leaves(),
+        };
+        let leaves = leaves.take(max(max_leaves, 1)).collect::<Result<Vec<_>>>()?;
+        let paths: Vec<Path> = after.into_iter().chain(leaves.iter().map(|leaf| leaf.path)).collect();
+        Ok(SnapshotChunk { proof: self.prove_paths(&paths)?, leaves })
+    }
+}
+
+impl<S: HubtStore, H: TreeHasher> Tree<S, H> {
+    /// Verifies `chunk` against `state.root`, then writes its leaves, their values and the internal
+    /// nodes they complete. The nodes on the right edge are written by the chunk ending the snapshot.
+    ///
+    /// The tree must be empty when the import starts, else `NotEmpty`. A chunk that does not follow
+    /// `state`, or whose leaves do not rebuild `state.root` once the snapshot ends, is `InvalidChunk`
+    /// and leaves both untouched.
+    pub fn import_chunk(&mut self, state: &mut ImportState, chunk: &SnapshotChunk) -> Result<()> {
+        if state.last.is_none() && !state.done && self.root()? != ZERO_HASH {
+            return Err(HubtError::NotEmpty);
+        }
+        let ends = check_chunk::<H>(self.mode(), state, chunk).ok_or(HubtError::InvalidChunk)?;
+
+        // Leaves without a value are only known by path, they must still come in path order
+        let mut next = state.clone();
+        let mut writes = Vec::new();
+        for leaf in &chunk.leaves {
+            if next.last.is_some_and(|(last, _)| last >= leaf.path) {
+                return Err(HubtError::InvalidChunk);
+            }
+            writes.push((NodeKey { path: leaf.path, len: 256 }, leaf.hash));
+            push_leaf::<H>(&mut next.edge, leaf.path, leaf.hash, &mut |key, hash| {
+                writes.push((key, hash));
+                Ok(())
+            })?;
+            next.last = Some((leaf.path, leaf.hash));
+        }
+        if ends {
+            let root = finish_edge::<H>(&mut next.edge, &mut |key, hash| {
+                writes.push((key, hash));
+                Ok(())
+            })?;
+            if root != next.root {
+                return Err(HubtError::InvalidChunk);
+            }
+            next.done = true;
+        }
+
+        self.store.start_batch()?;
+        for (key, hash) in writes {
+            self.store.put(key, hash)?;
+        }
+        for (path, (k, v)) in chunk.leaves.iter().filter_map(|leaf| Some((leaf.path, leaf.kv.as_ref()?))) {
+            self.store.put_value(path, k, v)?;
+        }
+        *state = next;
+        Ok(())
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::{Hardened, Sha256Hasher};
+    use crate::hubt_mem::{Hubt, ValueHubt};
+    use crate::test_util::key;
+    use crate::tree::Op;
+
+    type H = Hardened<Sha256Hasher>;
+
+    fn sync<S: HubtRead, D: HubtStore>(source: &Tree<S>, dest: &mut Tree<D>, max_leaves: usize) -> usize {
+        let mut state = ImportState::new(source.root().unwrap());
+        let mut chunks = 0;
+        while !state.done {
+            let chunk = source.export_chunk(state.after(), max_leaves).unwrap();
+            dest.import_chunk(&mut state, &chunk).unwrap();
+            chunks += 1;
+        }
+        chunks
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let mut source = ValueHubt::new();
+        source.batch_update((0..500u32).map(|i| Op::Insert(key(i), vec![i as u8; 5])).collect()).unwrap();
+
+        for max_leaves in [1, 7, 64, 1000] {
+            let mut dest = ValueHubt::new();
+            let chunks = sync(&source, &mut dest, max_leaves);
+            assert_eq!(chunks, 500usize.div_ceil(max_leaves));
+            assert!(dest.store.nodes == source.store.nodes && dest.store.values == source.store.values);
+        }
+
+        // Plain trees import the leaf hashes only, empty trees sync in one chunk
+        let mut dest = Hubt::new();
+        sync(&source, &mut dest, 100);
+        assert_eq!(dest.root().unwrap(), source.root().unwrap());
+        assert_eq!(sync(&Hubt::new(), &mut Hubt::new(), 10), 1);
+    }
+
+    #[test]
+    fn test_bad_chunks_are_rejected() {
+        let ops = || (0..200u32).map(|i| Op::Insert(key(i), b"v".to_vec())).collect();
+        // Plain trees without values can not vouch for a leaf's path
+        let mut plain = Hubt::new();
+        plain.batch_update(ops()).unwrap();
+        let chunk = plain.export_chunk(None, 50).unwrap();
+        assert_eq!(Hubt::new().import_chunk(&mut ImportState::new(plain.root().unwrap()), &chunk), Err(HubtError::InvalidChunk));
+
+        let mut source = Hubt::<H>::with_mode(KeyMode::Hashed);
+        source.batch_update(ops()).unwrap();
+        let mut dest = Hubt::<H>::with_mode(KeyMode::Hashed);
+        let mut state = ImportState::new(source.root().unwrap());
+        let first = source.export_chunk(None, 50).unwrap();
+        dest.import_chunk(&mut state, &first).unwrap();
+        let saved = state.clone();
+
+        // Proof for `leaves` following the last imported leaf
+        let prove_after = |leaves: &[Leaf]| {
+            let paths: Vec<Path> = saved.after().into_iter().chain(leaves.iter().map(|l| l.path)).collect();
+            source.prove_paths(&paths).unwrap()
+        };
+        let chunk = source.export_chunk(state.after(), 50).unwrap();
+        let mut skipped = chunk.clone();
+        skipped.leaves.remove(10);
+        skipped.proof = prove_after(&skipped.leaves);
+        let mut forged = chunk.clone();
+        forged.leaves[3].hash[0] ^= 1;
+        let mut gap = source.export_chunk(Some(chunk.leaves[0].path), 50).unwrap();
+        gap.proof = prove_after(&gap.leaves);
+
+        let mut moved = chunk.clone();
+        moved.leaves[3].path[31] ^= 1;
+        moved.proof.found[4].path = moved.leaves[3].path;
+        let mut swapped = chunk.clone();
+        swapped.leaves.swap(3, 4);
+        swapped.proof.found.swap(4, 5);
+
+        for bad in [skipped, forged, gap, first, moved, swapped] {
+            assert_eq!(dest.import_chunk(&mut state, &bad), Err(HubtError::InvalidChunk));
+            assert_eq!(state, saved);
+        }
+        // A snapshot can not start in the middle of the tree
+        assert_eq!(Hubt::<H>::with_mode(KeyMode::Hashed).import_chunk(&mut ImportState::new(saved.root), &chunk), Err(HubtError::InvalidChunk));
+
+        dest.import_chunk(&mut state, &chunk).unwrap();
+        while !state.done {
+            let chunk = source.export_chunk(state.after(), 50).unwrap();
+            dest.import_chunk(&mut state, &chunk).unwrap();
+        }
+        assert!(dest.store == source.store);
+    }
+
+    #[test]
+    fn test_import_checks_the_rebuilt_root() {
+        let mut source = Hubt::<H>::with_mode(KeyMode::Hashed);
+        source.batch_update((0..200u32).map(|i| Op::Insert(key(i), b"v".to_vec())).collect()).unwrap();
+        let first = source.export_chunk(None, 150).unwrap();
+
+        // Imports only start on an empty tree
+        let mut dest = Hubt::<H>::with_mode(KeyMode::Hashed);
+        dest.batch_update(vec![Op::Insert(b"k".to_vec(), b"v".to_vec())]).unwrap();
+        let before = dest.store.clone();
+        assert_eq!(dest.import_chunk(&mut ImportState::new(source.root().unwrap()), &first), Err(HubtError::NotEmpty));
+        assert!(dest.store == before);
+
+        // A resumed import with a corrupted edge is refused by the last chunk, before anything is written
+        let mut dest = Hubt::<H>::with_mode(KeyMode::Hashed);
+        let mut state = ImportState::new(source.root().unwrap());
+        dest.import_chunk(&mut state, &first).unwrap();
+        state.edge[0].1[0] ^= 1;
+        let saved = state.clone();
+        let before = dest.store.clone();
+        let last = source.export_chunk(state.after(), 150).unwrap();
+        assert_eq!(dest.import_chunk(&mut state, &last), Err(HubtError::InvalidChunk));
+        assert_eq!(state, saved);
+        assert!(!state.done && dest.store == before);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::bits::Path;
+use crate::error::Result;
+use crate::hasher::Hash;
+use rayon::prelude::*;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub struct NodeKey {
+    pub path: Path,
+    pub len: u16,
+}
+
+impl PartialOrd for NodeKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for NodeKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Big Endian sort: Path first, then Length
+        match self.path.cmp(&other.path) {
+            Ordering::Equal => self.len.cmp(&other.len),
+            other => other,
+        }
+    }
+}
+
+/// Ordered `NodeKey -> Hash` storage the tree engine runs on.
+///
+/// The topology is never stored, it is derived from the sort order of `NodeKey`,
+/// so a backend only needs point access plus seeking in both directions.
+/// Implement this to plug in another ordered key-value store (LMDB, sled, ...).
+/// Read and write failures are returned, never swallowed, the tree aborts on the first one.
+///
+/// Proving and reading roots only need `HubtRead`, so read-only views (e.g. a past version)
+/// implement just that half.
+pub trait HubtRead {
+    fn get(&self, key: &NodeKey) -> Result<Option<Hash>>;
+    /// Last entry strictly before `key`.
+    fn seek_prev(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>>;
+    /// First entry at or after `key`.
+    fn seek_next(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>>;
+    /// Key and value bytes of the leaf at `path`, only stores that keep values return them.
+    fn get_value(&self, _path: &Path) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        Ok(None)
+    }
+    /// True if the node stands for a subtree whose nodes are not stored, see `WitnessStore`.
+    fn is_opaque(&self, _key: &NodeKey) -> bool {
+        false
+    }
+}
+
+pub trait HubtStore: HubtRead {
+    fn put(&mut self, key: NodeKey, hash: Hash) -> Result<()>;
+    fn delete(&mut self, key: &NodeKey) -> Result<()>;
+    fn put_value(&mut self, _path: Path, _k: &[u8], _v: &[u8]) -> Result<()> {
+        Ok(())
+    }
+    fn delete_value(&mut self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+    /// Called by the tree before the writes of a batch, see `UndoStore`.
+    fn start_batch(&mut self) -> Result<()> {
+        Ok(())
+    }
+    /// Runs `f` over `nodes`, results in the same order. Stores that can be shared between
+    /// threads run it in parallel.
+    fn map_nodes<T: Send, F: Fn(&Self, &NodeKey) -> T + Sync>(&self, nodes: &[NodeKey], f: F) -> Vec<T>
+    where
+        Self: Sized,
+    {
+        nodes.iter().map(|node| f(self, node)).collect()
+    }
+}
+
+impl HubtRead for BTreeMap<NodeKey, Hash> {
+    fn get(&self, key: &NodeKey) -> Result<Option<Hash>> {
+        Ok(BTreeMap::get(self, key).copied())
+    }
+
+    fn seek_prev(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>> {
+        Ok(self.range(..*key).next_back().map(|(k, v)| (*k, *v)))
+    }
+
+    fn seek_next(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>> {
+        Ok(self.range((Bound::Included(*key), Bound::Unbounded)).next().map(|(k, v)| (*k, *v)))
+    }
+}
+
+impl HubtStore for BTreeMap<NodeKey, Hash> {
+    fn put(&mut self, key: NodeKey, hash: Hash) -> Result<()> {
+        self.insert(key, hash);
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &NodeKey) -> Result<()> {
+        self.remove(key);
+        Ok(())
+    }
+
+    fn map_nodes<T: Send, F: Fn(&Self, &NodeKey) -> T + Sync>(&self, nodes: &[NodeKey], f: F) -> Vec<T> {
+        nodes.par_iter().with_min_len(64).map(|node| f(self, node)).collect()
+    }
+}
@@ -0,0 +1,215 @@
+use crate::bits::{get_bit_be, lcp_be, mask_after_be, prefix_match_be, Path};
+use crate::error::Result;
+use crate::hasher::{Hash, KeyMode, TreeHasher, ZERO_HASH};
+use crate::multiproof::FoundNode;
+use crate::proof::{verify_structure, Proof, ProofNode};
+use crate::store::{HubtRead, NodeKey};
+use crate::tree::Tree;
+use std::cmp::min;
+
+/// Proof that a list of k/v pairs is everything under a bit prefix.
+///
+/// The verifier rebuilds the subtree of the pairs, so the pairs fix its hash and shape.
+/// `nodes` chain that subtree to the root, the deepest ancestor must be shorter than the
+/// prefix so no other branch of the tree can hold keys under it.
+/// When nothing is under the prefix, `boundary` is the node where the prefix leaves the
+/// tree, accepted by the same rules as a `Proof` of non-existence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubtreeProof {
+    pub root: Hash,
+    pub prefix: Path,
+    pub prefix_len: u16,
+    pub nodes: Vec<ProofNode>,
+    pub boundary: Option<FoundNode>,
+}
+
+/// Length and hash of the subtree holding exactly `leaves`, sorted by path and distinct.
+fn subtree_hash<H: TreeHasher>(leaves: &[(Path, Hash)]) -> (u16, Hash) {
+    if leaves.len() == 1 {
+        return (256, leaves[0].1);
+    }
+    let (_, len) = lcp_be(&leaves[0].0, &leaves[leaves.len() - 1].0);
+    let split = leaves.partition_point(|(path, _)| get_bit_be(path, len) == 0);
+    let bound = |leaves: &[(Path, Hash)]| {
+        let (len, hash) = subtree_hash::<H>(leaves);
+        let (path, _) = lcp_be(&leaves[0].0, &leaves[leaves.len() - 1].0);
+        H::bind(len, &path, &hash)
+    };
+    (len, H::node(&bound(&leaves[..split]), &bound(&leaves[split..])))
+}
+
+impl SubtreeProof {
+    /// True if `kvs` are all the leaves under the prefix of the tree at `root`, in any order.
+    pub fn verify<H: TreeHasher>(&self, mode: KeyMode, kvs: &[(Vec<u8>, Vec<u8>)]) -> bool {
+        let mut canonical = self.prefix;
+        mask_after_be(&mut canonical, self.prefix_len);
+        if self.prefix_len > 256 || canonical != self.prefix {
+            return false;
+        }
+        if self.root == ZERO_HASH {
+            return kvs.is_empty() && self.nodes.is_empty() && self.boundary.is_none();
+        }
+
+        let (path, len, hash) = match (&self.boundary, kvs.is_empty()) {
+            (Some(node), true) => (node.path, node.len, node.hash),
+            (None, false) => {
+                let mut leaves: Vec<(Path, Hash)> =
+                    kvs.iter().map(|(k, v)| (mode.path::<H>(k), H::leaf(k, v))).collect();
+                leaves.sort_unstable_by_key(|(path, _)| *path);
+                if leaves.windows(2).any(|w| w[0].0 == w[1].0)
+                    || leaves.iter().any(|(path, _)| !prefix_match_be(path, &self.prefix, self.prefix_len))
+                {
+                    return false;
+                }
+                let (len, hash) = subtree_hash::<H>(&leaves);
+                let mut path = leaves[0].0;
+                mask_after_be(&mut path, len);
+                (path, len, hash)
+            }
+            _ => return false,
+        };
+
+        let proof = Proof { root: self.root, nodes: self.nodes.clone(), path, hash, len, value: None };
+        if !proof.verify_integrity::<H>() || !verify_structure(&proof) {
+            return false;
+        }
+        match self.boundary {
+            // The prefix leaves the boundary's edge and no ancestor branches there, see `Proof::verify`
+            Some(_) => {
+                let (_, div_idx) = lcp_be(&self.prefix, &path);
+                div_idx < self.prefix_len && div_idx < len && !self.nodes.iter().any(|node| node.len == div_idx)
+            }
+            None => self.nodes.first().is_none_or(|node| node.len < self.prefix_len),
+        }
+    }
+}
+
+impl<S: HubtRead, H: TreeHasher> Tree<S, H> {
+    /// Proof that the leaves under the first `prefix_len` bits of `prefix` are complete, see `leaves_under`.
+    pub fn prove_subtree(&self, mut prefix: Path, prefix_len: u16) -> Result<SubtreeProof> {
+        let prefix_len = min(prefix_len, 256);
+        mask_after_be(&mut prefix, prefix_len);
+        let mut proof = SubtreeProof { root: self.root()?, prefix, prefix_len, nodes: Vec::new(), boundary: None };
+        if proof.root == ZERO_HASH {
+            return Ok(proof);
+        }
+
+        // Topmost node under the prefix, as `get_child` finds it
+        let top = self.store.seek_next(&NodeKey { path: prefix, len: prefix_len })?;
+        match top.filter(|(key, _)| prefix_match_be(&key.path, &prefix, prefix_len)) {
+            Some((key, _)) => proof.nodes = self.generate_proof_nodes(key.path, key.len)?,
+            None => {
+                if let Some((key, hash)) = self.find_longest_prefix_node(&prefix)? {
+                    proof.nodes = self.generate_proof_nodes(key.path, key.len)?;
+                    proof.boundary = Some(FoundNode { path: key.path, hash, len: key.len });
+                }
+            }
+        }
+        Ok(proof)
+    }
+
+    /// Same as `prove_subtree` for the keys starting with `ns` (up to 8 bytes), see `namespace_leaves`.
+    pub fn prove_namespace(&self, ns: &[u8]) -> Result<SubtreeProof> {
+        let ns_len = min(ns.len(), 8);
+        let mut prefix = [0u8; 32];
+        prefix[..ns_len].copy_from_slice(&ns[..ns_len]);
+        self.prove_subtree(prefix, ns_len as u16 * 8)
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::{Hardened, Sha256Hasher};
+    use crate::hubt_mem::{Hubt, ValueHubt};
+    use crate::tree::Op;
+
+    fn namespaced<H: TreeHasher>() -> ValueHubt<H> {
+        let mut hubt = ValueHubt::<H>::with_mode(KeyMode::Namespaced);
+        let mut ops = Vec::new();
+        for ns in [b"accounts", b"contract", b"contrac\x00", b"tx______"] {
+            for i in 0..30u32 {
+                ops.push(Op::Insert([&ns[..], &i.to_be_bytes()].concat(), i.to_be_bytes().to_vec()));
+            }
+        }
+        hubt.batch_update(ops).unwrap();
+        hubt
+    }
+
+    fn contents(hubt: &ValueHubt, ns: &[u8]) -> Vec<(Vec<u8>, Vec<u8>)> {
+        hubt.namespace_leaves(ns).map(|leaf| leaf.unwrap().kv.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_namespace_completeness() {
+        let hubt = namespaced::<Sha256Hasher>();
+        let verify = |proof: &SubtreeProof, kvs: &[(Vec<u8>, Vec<u8>)]| proof.verify::<Sha256Hasher>(KeyMode::Namespaced, kvs);
+
+        let proof = hubt.prove_namespace(b"contract").unwrap();
+        let mut kvs = contents(&hubt, b"contract");
+        assert_eq!(kvs.len(), 30);
+        assert!(verify(&proof, &kvs));
+        kvs.reverse();
+        assert!(verify(&proof, &kvs));
+
+        // Left out, extra, changed and duplicated pairs are all rejected
+        assert!(!verify(&proof, &kvs[1..]));
+        let mut extra = kvs.clone();
+        extra.push(([&b"contract"[..], &99u32.to_be_bytes()].concat(), b"v".to_vec()));
+        assert!(!verify(&proof, &extra));
+        let mut outside = kvs.clone();
+        outside.push(contents(&hubt, b"contrac\x00")[0].clone());
+        assert!(!verify(&proof, &outside));
+        let mut changed = kvs.clone();
+        changed[3].1 = b"changed".to_vec();
+        assert!(!verify(&proof, &changed));
+        let mut duplicated = kvs.clone();
+        duplicated.push(kvs[0].clone());
+        assert!(!verify(&proof, &duplicated));
+
+        // A proof for a wider prefix can not be passed off as the namespace
+        let mut wider = hubt.prove_subtree(proof.prefix, 56).unwrap();
+        assert!(verify(&wider, &[contents(&hubt, b"contract"), contents(&hubt, b"contrac\x00")].concat()));
+        wider.prefix_len = 64;
+        wider.prefix = proof.prefix;
+        assert!(!verify(&wider, &kvs));
+
+        // Empty namespaces prove their absence
+        let empty = hubt.prove_namespace(b"missing!").unwrap();
+        assert!(empty.boundary.is_some());
+        assert!(verify(&empty, &[]));
+        assert!(!verify(&empty, &kvs));
+        assert!(!verify(&proof, &[]));
+
+        // Whole tree, single leaf and empty tree
+        let all = contents(&hubt, b"");
+        assert_eq!(all.len(), 120);
+        assert!(verify(&hubt.prove_subtree([0u8; 32], 0).unwrap(), &all));
+        let path = KeyMode::Namespaced.path::<Sha256Hasher>(&all[5].0);
+        assert!(verify(&hubt.prove_subtree(path, 256).unwrap(), &all[5..6]));
+        assert!(Hubt::new().prove_namespace(b"contract").unwrap().verify::<Sha256Hasher>(KeyMode::Namespaced, &[]));
+    }
+
+    #[test]
+    fn test_subtree_positions_are_committed() {
+        type H = Hardened<Sha256Hasher>;
+        let hubt = namespaced::<H>();
+        let proof = hubt.prove_namespace(b"contract").unwrap();
+        let (top, _) = hubt.store.seek_next(&NodeKey { path: proof.prefix, len: 64 }).unwrap().unwrap();
+        let (left, _) = hubt.get_child(top.path, top.len, 0).unwrap().unwrap();
+
+        // The topmost node's left half passed off as everything under the prefix, its right half
+        // moved into a sibling above the prefix
+        let len = (proof.nodes[0].len + 1..64).find(|&i| get_bit_be(&left.path, i) == 0).unwrap();
+        let mut forged = proof.clone();
+        let hash = hubt.get_child_hash(top.path, top.len, 1).unwrap();
+        forged.nodes.insert(0, ProofNode { hash, direction: 1, len });
+        let kvs: Vec<_> = hubt.leaves_under(left.path, left.len).map(|leaf| leaf.unwrap().kv.unwrap()).collect();
+        assert!(kvs.len() < 30);
+        assert!(!forged.verify::<H>(KeyMode::Namespaced, &kvs));
+    }
+}
@@ -0,0 +1,6 @@
+//! Fixtures shared by the unit tests.
+
+/// Key `i` as 4 big endian bytes.
+pub fn key(i: u32) -> Vec<u8> {
+    i.to_be_bytes().to_vec()
+}
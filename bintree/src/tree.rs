@@ -0,0 +1,436 @@
+use crate::bits::{get_bit_be, lcp_be, mask_after_be, prefix_match_be, set_bit_be, Path};
+use crate::error::{HubtError, Result};
+use crate::hasher::{Hash, KeyMode, Sha256Hasher, TreeHasher, ZERO_HASH};
+use crate::proof::{Proof, ProofNode, VerifyStatus};
+use crate::store::{HubtRead, HubtStore, NodeKey};
+use rayon::prelude::*;
+use std::cmp::{min, Reverse};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::marker::PhantomData;
+
+/// A batch entry. Ops apply in order: conditions are checked against the leaf left by the
+/// earlier ops on the same key, if one fails the batch is rejected with `ConditionFailed`
+/// and nothing is written. The last op on a key decides its leaf.
+#[derive(Debug, Clone)]
+pub enum Op {
+    Insert(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+    /// Insert only if the key has no leaf.
+    InsertIfAbsent(Vec<u8>, Vec<u8>),
+    /// Replace the value only if the current one is `expected`.
+    CompareAndSwap { k: Vec<u8>, expected: Vec<u8>, v: Vec<u8> },
+    /// Delete only if the key has a leaf.
+    DeleteIfPresent(Vec<u8>),
+}
+
+impl Op {
+    pub fn key(&self) -> &[u8] {
+        match self {
+            Op::Insert(k, _) | Op::Delete(k) | Op::InsertIfAbsent(k, _) | Op::DeleteIfPresent(k) => k,
+            Op::CompareAndSwap { k, .. } => k,
+        }
+    }
+
+    /// New value, `None` for deletes.
+    pub fn value(&self) -> Option<&[u8]> {
+        match self {
+            Op::Insert(_, v) | Op::InsertIfAbsent(_, v) | Op::CompareAndSwap { v, .. } => Some(v),
+            Op::Delete(_) | Op::DeleteIfPresent(_) => None,
+        }
+    }
+
+    /// True if the condition holds with `old` as the current leaf hash.
+    fn holds<H: TreeHasher>(&self, old: Option<Hash>) -> bool {
+        match self {
+            Op::Insert(..) | Op::Delete(_) => true,
+            Op::InsertIfAbsent(..) => old.is_none(),
+            Op::CompareAndSwap { k, expected, .. } => old == Some(H::leaf(k, expected)),
+            Op::DeleteIfPresent(_) => old.is_some(),
+        }
+    }
+}
+
+/// What an `Op` did to its leaf, against the leaf before it, see `Op`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpOutcome {
+    Inserted { new: Hash },
+    Updated { old: Hash, new: Hash },
+    Deleted { old: Hash },
+    DeletedMissing,
+}
+
+/// Returned by `Tree::batch_update`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchResult {
+    pub old_root: Hash,
+    pub new_root: Hash,
+    /// One per op, in input order.
+    pub outcomes: Vec<OpOutcome>,
+    /// Internal nodes put (new or rehashed) and removed by the rehash.
+    pub nodes_written: usize,
+    pub nodes_removed: usize,
+    /// Every node written or removed, leaves included, sorted. See `Tree::change_set`.
+    pub changed: Vec<NodeKey>,
+}
+
+/// Topmost node under `p_path[..p_len] || dir`, see `Tree::get_child_hash`.
+pub(crate) fn child<S: HubtRead>(store: &S, p_path: Path, p_len: u16, dir: u8) -> Result<Option<(NodeKey, Hash)>> {
+    let mut target_path = p_path;
+    set_bit_be(&mut target_path, p_len, dir);
+    mask_after_be(&mut target_path, p_len + 1);
+    let target_key = NodeKey { path: target_path, len: p_len + 1 };
+
+    if let Some((f_key, hash)) = store.seek_next(&target_key)? {
+        if prefix_match_be(&f_key.path, &target_path, p_len + 1) {
+            return Ok(Some((f_key, hash)));
+        }
+    }
+    Ok(None)
+}
+
+/// Hash the parent of `key` and the root commit to, see `TreeHasher::bind`.
+/// Opaque nodes of a witness already hold it, placeholders stay `ZERO_HASH`.
+pub(crate) fn bound<S: HubtRead, H: TreeHasher>(store: &S, key: &NodeKey, hash: Hash) -> Hash {
+    if hash == ZERO_HASH || store.is_opaque(key) {
+        hash
+    } else {
+        H::bind(key.len, &key.path, &hash)
+    }
+}
+
+// ============================================================================
+// TREE ENGINE
+// ============================================================================
+
+/// The HUBT algorithm, written once against any `HubtStore`.
+///
+/// `Hubt` (BTreeMap) and `RocksHubt` (RocksDB transaction) are this engine over their store.
+pub struct Tree<S, H = Sha256Hasher> {
+    pub store: S,
+    mode: KeyMode,
+    hasher: PhantomData<H>,
+}
+
+impl<S: HubtRead, H: TreeHasher> Tree<S, H> {
+    pub fn with_store(store: S, mode: KeyMode) -> Self {
+        Tree { store, mode, hasher: PhantomData }
+    }
+
+    pub fn mode(&self) -> KeyMode {
+        self.mode
+    }
+
+    pub fn root(&self) -> Result<Hash> {
+        // Root is the smallest node (00..00 len 0 side of the sort order, or the only leaf)
+        let first = NodeKey { path: [0u8; 32], len: 0 };
+        Ok(self.store.seek_next(&first)?.map_or(ZERO_HASH, |(key, h)| bound::<S, H>(&self.store, &key, h)))
+    }
+
+    /// Closest leaf (or opaque subtree) before / after `key`, skipping the internal nodes sorted in between.
+    fn neighbor_leaf(&self, key: NodeKey, forward: bool) -> Result<Option<NodeKey>> {
+        let mut cursor = key;
+        loop {
+            let found = if forward {
+                // Nothing sorts between (path, len) and (path, len + 1)
+                self.store.seek_next(&NodeKey { path: cursor.path, len: cursor.len + 1 })?
+            } else {
+                self.store.seek_prev(&cursor)?
+            };
+            match found {
+                None => return Ok(None),
+                Some((k, _)) if k.len == 256 || self.store.is_opaque(&k) => return Ok(Some(k)),
+                Some((k, _)) => cursor = k,
+            }
+        }
+    }
+
+    fn collect_dirty_ancestors(&self, target_path: Path, acc: &mut BTreeSet<NodeKey>) -> Result<()> {
+        let mut cursor = NodeKey { path: target_path, len: 256 };
+        loop {
+            match self.store.seek_prev(&cursor)? {
+                None => return Ok(()),
+                Some((k, _)) => {
+                    if prefix_match_be(&target_path, &k.path, k.len) {
+                        acc.insert(k);
+                        cursor = k;
+                    } else {
+                        let (lcp_path, lcp_len) = lcp_be(&target_path, &k.path);
+                        let jump_key = NodeKey { path: lcp_path, len: lcp_len + 1 };
+                        cursor = if jump_key < k { jump_key } else { k };
+                    }
+                }
+            }
+        }
+    }
+
+    /// Topmost node under `p_path[..p_len] || dir`, it may sit exactly on the target key.
+    /// Its hash is bound to its position, as the parent commits to it.
+    pub(crate) fn get_child_hash(&self, p_path: Path, p_len: u16, dir: u8) -> Result<Hash> {
+        Ok(self.get_child(p_path, p_len, dir)?.map_or(ZERO_HASH, |(key, hash)| bound::<S, H>(&self.store, &key, hash)))
+    }
+
+    pub(crate) fn get_child(&self, p_path: Path, p_len: u16, dir: u8) -> Result<Option<(NodeKey, Hash)>> {
+        child(&self.store, p_path, p_len, dir)
+    }
+
+    // ========================================================================
+    // UNIFIED PROOF LOGIC
+    // ========================================================================
+
+    /// Generates a single Proof struct that covers Inclusion, Mismatch, or Non-Existence.
+    ///
+    /// The logic detects the state of `k` in the tree:
+    /// 1. Finds the node matching `k` (or the longest matching prefix node).
+    /// 2. Generates the merkle path to that node.
+    /// 3. Returns the proof containing the found node's path and hash (and the value, if kept).
+    pub fn prove(&self, k: Vec<u8>) -> Result<Proof> {
+        let target_path = self.mode.path::<H>(&k);
+
+        // Find the node that actually exists (Exact match OR Longest Prefix)
+        let (found_key, found_hash) = match self.find_longest_prefix_node(&target_path)? {
+            Some((key, hash)) => (key, hash),
+            None => {
+                // Empty tree case
+                return Ok(Proof {
+                    root: ZERO_HASH,
+                    nodes: vec![],
+                    path: ZERO_HASH,
+                    hash: ZERO_HASH,
+                    len: 0,
+                    value: None,
+                });
+            }
+        };
+
+        let value = if found_key.len == 256 && found_key.path == target_path {
+            self.store.get_value(&target_path)?.filter(|(key, _)| *key == k).map(|(_, v)| v)
+        } else {
+            None
+        };
+
+        Ok(Proof {
+            root: self.root()?,
+            nodes: self.generate_proof_nodes(found_key.path, found_key.len)?,
+            path: found_key.path,
+            hash: found_hash,
+            len: found_key.len,
+            value,
+        })
+    }
+
+    pub(crate) fn generate_proof_nodes(&self, path: Path, len: u16) -> Result<Vec<ProofNode>> {
+        let mut nodes = Vec::new();
+        for anc in self.ancestors(path, len)? {
+            let my_dir = get_bit_be(&path, anc.len);
+            let sibling_dir = 1 - my_dir;
+            nodes.push(ProofNode {
+                hash: self.get_child_hash(anc.path, anc.len, sibling_dir)?,
+                direction: sibling_dir,
+                len: anc.len,
+            });
+        }
+        Ok(nodes)
+    }
+
+    /// Internal nodes above `(path, len)`, deepest first.
+    pub(crate) fn ancestors(&self, path: Path, len: u16) -> Result<Vec<NodeKey>> {
+        let mut ancestors = Vec::new();
+        let mut cursor = NodeKey { path, len: 256 };
+
+        loop {
+            match self.store.seek_prev(&cursor)? {
+                None => break,
+                Some((k, _)) => {
+                    if prefix_match_be(&path, &k.path, k.len) {
+                        if k.len < len { ancestors.push(k); }
+                        cursor = k;
+                    } else {
+                        let (lcp_p, lcp_l) = lcp_be(&path, &k.path);
+                        let jump = NodeKey { path: lcp_p, len: lcp_l + 1 };
+                        cursor = if jump < k { jump } else { k };
+                    }
+                }
+            }
+        }
+        ancestors.sort_unstable_by_key(|n| Reverse(n.len));
+        Ok(ancestors)
+    }
+
+    pub(crate) fn find_longest_prefix_node(&self, target: &Path) -> Result<Option<(NodeKey, Hash)>> {
+        let s_key = NodeKey { path: *target, len: 256 };
+        let next = self.store.seek_next(&s_key)?;
+        if let Some((k, h)) = next {
+            if k == s_key { return Ok(Some((k, h))); }
+        }
+        let prev = self.store.seek_prev(&s_key)?;
+
+        Ok(match (prev, next) {
+            (None, None) => None,
+            (None, Some(n)) => Some(n),
+            (Some(p), None) => Some(p),
+            (Some((pk, ph)), Some((nk, nh))) => {
+                let (_, rp) = lcp_be(target, &pk.path);
+                let (_, rn) = lcp_be(target, &nk.path);
+                if min(rp, pk.len) >= min(rn, nk.len) { Some((pk, ph)) } else { Some((nk, nh)) }
+            }
+        })
+    }
+
+    // ========================================================================
+    // UNIFIED VERIFICATION
+    // ========================================================================
+
+    /// Same as `verify`, for proofs generated by a tree using `mode` and this tree's hasher.
+    pub fn verify_with_mode(mode: KeyMode, proof: &Proof, k: Vec<u8>, v: Vec<u8>) -> VerifyStatus {
+        proof.verify::<H>(mode, &k, &v)
+    }
+}
+
+impl<S: HubtStore, H: TreeHasher> Tree<S, H> {
+    // --- BATCH UPDATE ---
+    pub fn batch_update(&mut self, ops: Vec<Op>) -> Result<BatchResult> {
+        let old_root = self.root()?;
+
+        // 1. Prepare Ops (Parallel Hash)
+        let mode = self.mode;
+        let mut prepared: Vec<(bool, Path, Hash, Op)> = ops.into_par_iter().map(|op| {
+            match op.value() {
+                Some(v) => (true, mode.path::<H>(op.key()), H::leaf(op.key(), v), op),
+                None => (false, mode.path::<H>(op.key()), ZERO_HASH, op)
+            }
+        }).collect();
+
+        // Conditions and outcomes read the old leaves before anything is written, then follow
+        // the earlier ops of the batch on the same path
+        let leaf_keys: Vec<NodeKey> = prepared.iter().map(|op| NodeKey { path: op.1, len: 256 }).collect();
+        let old_leaves = self.store.map_nodes(&leaf_keys, |store, key| store.get(key));
+        let mut leaves: BTreeMap<Path, (Option<Hash>, Option<Hash>)> = BTreeMap::new();
+        let mut outcomes = Vec::with_capacity(prepared.len());
+        for (index, ((is_ins, path, new, op), old)) in prepared.iter().zip(old_leaves).enumerate() {
+            let (_, current) = match leaves.entry(*path) {
+                Entry::Occupied(leaf) => leaf.into_mut(),
+                Entry::Vacant(leaf) => {
+                    let old = old?;
+                    leaf.insert((old, old))
+                }
+            };
+            if !op.holds::<H>(*current) {
+                return Err(HubtError::ConditionFailed { index, key: op.key().to_vec() });
+            }
+            outcomes.push(match (*is_ins, *current) {
+                (true, None) => OpOutcome::Inserted { new: *new },
+                (true, Some(old)) => OpOutcome::Updated { old, new: *new },
+                (false, Some(old)) => OpOutcome::Deleted { old },
+                (false, None) => OpOutcome::DeletedMissing,
+            });
+            *current = is_ins.then_some(*new);
+        }
+        let mut changed: Vec<NodeKey> = leaves.into_iter()
+            .filter(|(_, leaf)| *leaf != (None, None))
+            .map(|(path, _)| NodeKey { path, len: 256 })
+            .collect();
+        self.store.start_batch()?;
+
+        // Stable, so ops on one path stay in input order and the last one is kept
+        prepared.par_sort_by_key(|op| op.1);
+        let mut last: Vec<(bool, Path, Hash, Op)> = Vec::with_capacity(prepared.len());
+        for op in prepared {
+            match last.last_mut() {
+                Some(prev) if prev.1 == op.1 => *prev = op,
+                _ => last.push(op),
+            }
+        }
+        let prepared = last;
+
+        // 2. Remove Old Leaves
+        for (is_ins, p, _, _) in &prepared {
+            if !*is_ins {
+                self.store.delete(&NodeKey { path: *p, len: 256 })?;
+                self.store.delete_value(p)?;
+            }
+        }
+
+        // 3. Insert New Leaves
+        let mut dirty_set = BTreeSet::new();
+        for (_, p, l, op) in &prepared {
+            if let Some(v) = op.value() {
+                self.store.put(NodeKey { path: *p, len: 256 }, *l)?;
+                self.store.put_value(*p, op.key(), v)?;
+            }
+        }
+
+        // 4. Split Points against the neighbouring leaves (Skeleton)
+        for (is_ins, p, _, _) in &prepared {
+            if *is_ins {
+                self.ensure_split_points(*p, &mut dirty_set)?;
+            }
+        }
+
+        // 5. Mark Ancestors Dirty
+        for (_, p, _, _) in &prepared {
+            self.collect_dirty_ancestors(*p, &mut dirty_set)?;
+        }
+
+        // 6. Rehash Bottom-Up
+        let (nodes_written, nodes_removed) = self.rehash_and_prune(dirty_set, &mut changed)?;
+        changed.sort_unstable();
+        changed.dedup();
+        Ok(BatchResult { old_root, new_root: self.root()?, outcomes, nodes_written, nodes_removed, changed })
+    }
+
+    /// Every branch node is the LCP of two leaves adjacent in path order, so a new leaf
+    /// only needs split points with its previous and next leaf.
+    pub(crate) fn ensure_split_points(&mut self, path: Path, dirty: &mut BTreeSet<NodeKey>) -> Result<()> {
+        let key = NodeKey { path, len: 256 };
+        if let Some(n_key) = self.neighbor_leaf(key, false)? {
+            let (lcp_p, lcp_l) = lcp_be(&path, &n_key.path);
+            self.ensure_node_exists(NodeKey { path: lcp_p, len: lcp_l }, dirty)?;
+        }
+        if let Some(n_key) = self.neighbor_leaf(key, true)? {
+            let (lcp_p, lcp_l) = lcp_be(&path, &n_key.path);
+            self.ensure_node_exists(NodeKey { path: lcp_p, len: lcp_l }, dirty)?;
+        }
+        Ok(())
+    }
+
+    /// Inserts a `ZERO_HASH` placeholder, the real hash is filled in by `rehash_and_prune`.
+    pub(crate) fn ensure_node_exists(&mut self, key: NodeKey, dirty: &mut BTreeSet<NodeKey>) -> Result<()> {
+        if self.store.get(&key)?.is_none() {
+            self.store.put(key, ZERO_HASH)?;
+            dirty.insert(key);
+        }
+        Ok(())
+    }
+
+    /// Dirty nodes of one length sit under disjoint prefixes and only read longer nodes,
+    /// so each level is hashed through `HubtStore::map_nodes` and written before the next.
+    /// Returns the number of nodes written and removed, both are added to `changed`.
+    fn rehash_and_prune(&mut self, dirty_nodes: BTreeSet<NodeKey>, changed: &mut Vec<NodeKey>) -> Result<(usize, usize)> {
+        let mut sorted_nodes: Vec<NodeKey> = dirty_nodes.into_iter().filter(|n| n.len < 256).collect();
+        sorted_nodes.sort_by_key(|n| Reverse(n.len));
+
+        let (mut written, mut removed) = (0, 0);
+        for level in sorted_nodes.chunk_by(|a, b| a.len == b.len) {
+            let hashes = self.store.map_nodes(level, |store, node| -> Result<Option<Hash>> {
+                let l_hash = child(store, node.path, node.len, 0)?.map_or(ZERO_HASH, |(k, h)| bound::<_, H>(store, &k, h));
+                let r_hash = child(store, node.path, node.len, 1)?.map_or(ZERO_HASH, |(k, h)| bound::<_, H>(store, &k, h));
+                Ok((l_hash != ZERO_HASH && r_hash != ZERO_HASH).then(|| H::node(&l_hash, &r_hash)))
+            });
+            for (node, hash) in level.iter().zip(hashes) {
+                changed.push(*node);
+                match hash? {
+                    Some(hash) => {
+                        self.store.put(*node, hash)?;
+                        written += 1;
+                    }
+                    None => {
+                        self.store.delete(node)?;
+                        removed += 1;
+                    }
+                }
+            }
+        }
+        Ok((written, removed))
+    }
+}
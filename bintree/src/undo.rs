@@ -0,0 +1,217 @@
+use crate::bits::Path;
+use crate::error::{HubtError, Result};
+use crate::hasher::{Hash, TreeHasher};
+use crate::store::{HubtRead, HubtStore, NodeKey};
+use crate::tree::Tree;
+
+/// What a write replaced, restored when its batch is reverted.
+#[derive(Debug, Clone)]
+enum Undo {
+    Node(NodeKey, Option<Hash>),
+    Value(Path, Option<(Vec<u8>, Vec<u8>)>),
+}
+
+/// Store `S` journaling the old node hashes and values each batch overwrites, see `Tree::revert_to`.
+///
+/// A batch starts at `HubtStore::start_batch`, writes made outside of the tree's batches are
+/// journaled with the batch before them. Checkpoints count batches since the store was created.
+pub struct UndoStore<S> {
+    pub inner: S,
+    journal: Vec<Undo>,
+    /// Start of each batch in `journal`.
+    batches: Vec<usize>,
+    /// Batches dropped by `release`.
+    released: u64,
+}
+
+impl<S> UndoStore<S> {
+    pub fn new(inner: S) -> Self {
+        UndoStore { inner, journal: Vec::new(), batches: Vec::new(), released: 0 }
+    }
+
+    fn record(&mut self, undo: Undo) {
+        if !self.batches.is_empty() {
+            self.journal.push(undo);
+        }
+    }
+}
+
+impl<S: HubtRead> HubtRead for UndoStore<S> {
+    fn get(&self, key: &NodeKey) -> Result<Option<Hash>> {
+        self.inner.get(key)
+    }
+
+    fn seek_prev(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>> {
+        self.inner.seek_prev(key)
+    }
+
+    fn seek_next(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>> {
+        self.inner.seek_next(key)
+    }
+
+    fn get_value(&self, path: &Path) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.inner.get_value(path)
+    }
+}
+
+impl<S: HubtStore> HubtStore for UndoStore<S> {
+    fn put(&mut self, key: NodeKey, hash: Hash) -> Result<()> {
+        let old = self.inner.get(&key)?;
+        self.inner.put(key, hash)?;
+        self.record(Undo::Node(key, old));
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &NodeKey) -> Result<()> {
+        if let Some(old) = self.inner.get(key)? {
+            self.inner.delete(key)?;
+            self.record(Undo::Node(*key, Some(old)));
+        }
+        Ok(())
+    }
+
+    fn put_value(&mut self, path: Path, k: &[u8], v: &[u8]) -> Result<()> {
+        let old = self.inner.get_value(&path)?;
+        self.inner.put_value(path, k, v)?;
+        self.record(Undo::Value(path, old));
+        Ok(())
+    }
+
+    fn delete_value(&mut self, path: &Path) -> Result<()> {
+        if let Some(old) = self.inner.get_value(path)? {
+            self.inner.delete_value(path)?;
+            self.record(Undo::Value(*path, Some(old)));
+        }
+        Ok(())
+    }
+
+    fn start_batch(&mut self) -> Result<()> {
+        self.batches.push(self.journal.len());
+        self.inner.start_batch()
+    }
+}
+
+impl<S: HubtStore, H: TreeHasher> Tree<UndoStore<S>, H> {
+    /// Number of batches applied so far, pass it to `revert_to` to come back to the current state.
+    pub fn checkpoint(&self) -> u64 {
+        self.store.released + self.store.batches.len() as u64
+    }
+
+    /// Undoes the last batch and returns the root before it.
+    pub fn revert_last(&mut self) -> Result<Hash> {
+        let checkpoint = self.checkpoint();
+        self.revert_to(checkpoint.checked_sub(1).ok_or(HubtError::UnknownCheckpoint(0))?)
+    }
+
+    /// Undoes every batch after `checkpoint`, restoring the exact store and root of that point.
+    pub fn revert_to(&mut self, checkpoint: u64) -> Result<Hash> {
+        if checkpoint < self.store.released || checkpoint > self.checkpoint() {
+            return Err(HubtError::UnknownCheckpoint(checkpoint));
+        }
+        let keep = (checkpoint - self.store.released) as usize;
+        while self.store.batches.len() > keep {
+            let start = self.store.batches[self.store.batches.len() - 1];
+            while self.store.journal.len() > start {
+                match self.store.journal.pop().expect("journal is longer than start") {
+                    Undo::Node(key, Some(hash)) => self.store.inner.put(key, hash)?,
+                    Undo::Node(key, None) => self.store.inner.delete(&key)?,
+                    Undo::Value(path, Some((k, v))) => self.store.inner.put_value(path, &k, &v)?,
+                    Undo::Value(path, None) => self.store.inner.delete_value(&path)?,
+                }
+            }
+            self.store.batches.pop();
+        }
+        self.root()
+    }
+
+    /// Drops the journal of the batches before `checkpoint`, they can not be reverted anymore.
+    pub fn release(&mut self, checkpoint: u64) -> Result<()> {
+        if checkpoint > self.checkpoint() {
+            return Err(HubtError::UnknownCheckpoint(checkpoint));
+        }
+        if checkpoint <= self.store.released {
+            return Ok(());
+        }
+        let drop = (checkpoint - self.store.released) as usize;
+        let start = self.store.batches.get(drop).copied().unwrap_or(self.store.journal.len());
+        self.store.journal.drain(..start);
+        self.store.batches.drain(..drop);
+        self.store.batches.iter_mut().for_each(|batch| *batch -= start);
+        self.store.released = checkpoint;
+        Ok(())
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::{KeyMode, Sha256Hasher};
+    use crate::hubt_mem::{Hubt, UndoHubt};
+    use crate::tree::Op;
+    use crate::values::WithValues;
+    use std::collections::BTreeMap;
+
+    fn batch(range: std::ops::Range<u32>, v: &[u8]) -> Vec<Op> {
+        range.map(|i| Op::Insert(i.to_be_bytes().to_vec(), v.to_vec())).collect()
+    }
+
+    #[test]
+    fn test_revert_restores_exact_store() {
+        let mut hubt = UndoHubt::new();
+        let mut states = vec![(hubt.store.inner.clone(), hubt.root().unwrap())];
+        let batches = vec![
+            batch(0..300, b"a"),
+            batch(200..400, b"b"),
+            (0..400u32).step_by(3).map(|i| Op::Delete(i.to_be_bytes().to_vec())).collect(),
+            vec![Op::Delete(b"missing".to_vec())],
+        ];
+        for ops in batches {
+            hubt.batch_update(ops).unwrap();
+            states.push((hubt.store.inner.clone(), hubt.root().unwrap()));
+        }
+        assert_eq!(hubt.checkpoint(), 4);
+
+        assert_eq!(hubt.revert_last().unwrap(), states[3].1);
+        assert!(hubt.store.inner == states[3].0);
+        assert_eq!(hubt.revert_to(1).unwrap(), states[1].1);
+        assert!(hubt.store.inner == states[1].0);
+        assert_eq!(hubt.revert_to(2), Err(HubtError::UnknownCheckpoint(2)));
+
+        // Later batches journal on top of the reverted state
+        hubt.batch_update(batch(500..600, b"c")).unwrap();
+        let mut expected = Hubt::new();
+        expected.batch_update(batch(0..300, b"a")).unwrap();
+        expected.batch_update(batch(500..600, b"c")).unwrap();
+        assert!(hubt.store.inner == expected.store);
+
+        // Released batches are final
+        hubt.release(2).unwrap();
+        assert_eq!(hubt.revert_to(1), Err(HubtError::UnknownCheckpoint(1)));
+        assert_eq!(hubt.revert_last(), Err(HubtError::UnknownCheckpoint(1)));
+        assert!(hubt.store.inner == expected.store);
+        assert!(hubt.store.journal.is_empty());
+        hubt.batch_update(batch(0..10, b"d")).unwrap();
+        assert_eq!(hubt.revert_last().unwrap(), expected.root().unwrap());
+        assert_eq!(hubt.revert_last(), Err(HubtError::UnknownCheckpoint(1)));
+        assert_eq!(UndoHubt::new().revert_last(), Err(HubtError::UnknownCheckpoint(0)));
+    }
+
+    #[test]
+    fn test_revert_restores_values() {
+        let store = UndoStore::new(WithValues { nodes: BTreeMap::new(), values: BTreeMap::new() });
+        let mut hubt = Tree::<_, Sha256Hasher>::with_store(store, KeyMode::Hashed);
+        hubt.batch_update(batch(0..50, b"a")).unwrap();
+        let checkpoint = hubt.checkpoint();
+        let values = hubt.store.inner.values.clone();
+
+        hubt.batch_update(batch(25..75, b"b")).unwrap();
+        hubt.batch_update(vec![Op::Delete(3u32.to_be_bytes().to_vec())]).unwrap();
+        hubt.revert_to(checkpoint).unwrap();
+        assert!(hubt.store.inner.values == values);
+        assert_eq!(hubt.store.inner.get_value(&KeyMode::Hashed.path::<Sha256Hasher>(&3u32.to_be_bytes())).unwrap().unwrap().1, b"a");
+    }
+}
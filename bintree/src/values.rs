@@ -0,0 +1,120 @@
+use crate::bits::Path;
+use crate::error::Result;
+use crate::hasher::{Hash, TreeHasher};
+use crate::store::{HubtRead, HubtStore, NodeKey};
+use crate::tree::Tree;
+use std::collections::BTreeMap;
+
+/// Original key and value bytes of each leaf, by leaf path.
+pub trait LeafValues {
+    fn get(&self, path: &Path) -> Result<Option<(Vec<u8>, Vec<u8>)>>;
+    fn put(&mut self, path: Path, k: &[u8], v: &[u8]) -> Result<()>;
+    fn delete(&mut self, path: &Path) -> Result<()>;
+}
+
+impl LeafValues for BTreeMap<Path, (Vec<u8>, Vec<u8>)> {
+    fn get(&self, path: &Path) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        Ok(BTreeMap::get(self, path).cloned())
+    }
+
+    fn put(&mut self, path: Path, k: &[u8], v: &[u8]) -> Result<()> {
+        self.insert(path, (k.to_vec(), v.to_vec()));
+        Ok(())
+    }
+
+    fn delete(&mut self, path: &Path) -> Result<()> {
+        self.remove(path);
+        Ok(())
+    }
+}
+
+/// Node store `S` that also keeps the leaf key/value bytes in `V`.
+pub struct WithValues<S, V> {
+    pub nodes: S,
+    pub values: V,
+}
+
+impl<S: HubtRead, V: LeafValues> HubtRead for WithValues<S, V> {
+    fn get(&self, key: &NodeKey) -> Result<Option<Hash>> {
+        self.nodes.get(key)
+    }
+
+    fn seek_prev(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>> {
+        self.nodes.seek_prev(key)
+    }
+
+    fn seek_next(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>> {
+        self.nodes.seek_next(key)
+    }
+
+    fn get_value(&self, path: &Path) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
+        self.values.get(path)
+    }
+}
+
+impl<S: HubtStore, V: LeafValues> HubtStore for WithValues<S, V> {
+    fn put(&mut self, key: NodeKey, hash: Hash) -> Result<()> {
+        self.nodes.put(key, hash)
+    }
+
+    fn delete(&mut self, key: &NodeKey) -> Result<()> {
+        self.nodes.delete(key)
+    }
+
+    fn put_value(&mut self, path: Path, k: &[u8], v: &[u8]) -> Result<()> {
+        self.values.put(path, k, v)
+    }
+
+    fn delete_value(&mut self, path: &Path) -> Result<()> {
+        self.values.delete(path)
+    }
+}
+
+impl<S: HubtRead, V: LeafValues, H: TreeHasher> Tree<WithValues<S, V>, H> {
+    /// Value stored under `k`.
+    pub fn get(&self, k: &[u8]) -> Result<Option<Vec<u8>>> {
+        let path = self.mode().path::<H>(k);
+        Ok(self.store.values.get(&path)?.filter(|(key, _)| key == k).map(|(_, v)| v))
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use crate::hubt_mem::{Hubt, ValueHubt};
+    use crate::proof::VerifyStatus;
+    use crate::tree::Op;
+
+    #[test]
+    fn test_get_and_prove_values() {
+        let mut plain = Hubt::new();
+        let mut hubt = ValueHubt::new();
+        let ops: Vec<Op> = (0..50u32).map(|i| Op::Insert(format!("key:{}", i).into_bytes(), format!("val:{}", i).into_bytes())).collect();
+        plain.batch_update(ops.clone()).unwrap();
+        hubt.batch_update(ops).unwrap();
+        // Keeping values does not change the commitment
+        assert_eq!(hubt.root().unwrap(), plain.root().unwrap());
+
+        assert_eq!(hubt.get(b"key:7").unwrap(), Some(b"val:7".to_vec()));
+        assert_eq!(hubt.get(b"key:99").unwrap(), None);
+
+        hubt.batch_update(vec![Op::Insert(b"key:7".to_vec(), b"new".to_vec()), Op::Delete(b"key:8".to_vec())]).unwrap();
+        assert_eq!(hubt.get(b"key:7").unwrap(), Some(b"new".to_vec()));
+        assert_eq!(hubt.get(b"key:8").unwrap(), None);
+        assert_eq!(hubt.store.values.len(), 49);
+
+        let proof = hubt.prove(b"key:7".to_vec()).unwrap();
+        assert_eq!(proof.value, Some(b"new".to_vec()));
+        assert_eq!(Hubt::verify(&proof, b"key:7".to_vec(), proof.value.clone().unwrap()), VerifyStatus::Included);
+
+        let proof = hubt.prove(b"key:8".to_vec()).unwrap();
+        assert_eq!(proof.value, None);
+        assert_eq!(Hubt::verify(&proof, b"key:8".to_vec(), vec![]), VerifyStatus::NonExistence);
+
+        // Plain trees prove without values
+        assert_eq!(plain.prove(b"key:7".to_vec()).unwrap().value, None);
+    }
+}
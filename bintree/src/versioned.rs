@@ -0,0 +1,526 @@
+use crate::diff::Diff;
+use crate::error::{HubtError, Result};
+use crate::hasher::{Hash, TreeHasher};
+use crate::proof::Proof;
+use crate::store::{HubtRead, HubtStore, NodeKey};
+use crate::tree::{Op, Tree};
+use std::cmp::{max, Ordering};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A node write, kept per version: `(node, version)`, sorted by node then version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionedKey {
+    pub node: NodeKey,
+    pub version: u64,
+}
+
+impl PartialOrd for VersionedKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for VersionedKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match self.node.cmp(&other.node) {
+            Ordering::Equal => self.version.cmp(&other.version),
+            other => other,
+        }
+    }
+}
+
+/// Copy-on-write node storage behind a `VersionedStore`.
+///
+/// Nodes are never overwritten across versions: every write is a new
+/// `(node, version) -> hash` entry, a delete is a `None` tombstone.
+pub trait VersionedBackend {
+    /// Last entry strictly before `key`.
+    fn entry_prev(&self, key: &VersionedKey) -> Result<Option<(VersionedKey, Option<Hash>)>>;
+    /// First entry at or after `key`.
+    fn entry_next(&self, key: &VersionedKey) -> Result<Option<(VersionedKey, Option<Hash>)>>;
+    fn put_entry(&mut self, key: VersionedKey, value: Option<Hash>) -> Result<()>;
+    fn delete_entry(&mut self, key: &VersionedKey) -> Result<()>;
+    /// Latest committed version, 0 before the first commit.
+    fn head(&self) -> Result<u64>;
+    fn set_head(&mut self, version: u64) -> Result<()>;
+    fn prune_state(&self) -> Result<PruneState>;
+    fn set_prune_state(&mut self, state: &PruneState) -> Result<()>;
+}
+
+// ============================================================================
+// PRUNING
+// ============================================================================
+
+/// Which committed versions survive pruning. The latest version is always kept.
+#[derive(Debug, Clone, Default)]
+pub struct PrunePolicy {
+    /// Keep the newest `n` versions, `None` keeps every version.
+    pub keep_last: Option<u64>,
+    /// Also keep every version divisible by `k` as a checkpoint.
+    pub checkpoint_every: Option<u64>,
+    /// Versions kept regardless of age, a version that is already pruned stays pruned.
+    pub pinned: BTreeSet<u64>,
+}
+
+impl PrunePolicy {
+    pub fn retains(&self, version: u64, head: u64) -> bool {
+        version == 0
+            || version >= head
+            || self.keep_last.is_none_or(|n| version + n > head)
+            || self.checkpoint_every.is_some_and(|k| version.is_multiple_of(k))
+            || self.pinned.contains(&version)
+    }
+}
+
+/// Persisted pruning progress: versions below `floor` are gone unless listed in `kept`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PruneState {
+    pub floor: u64,
+    pub kept: BTreeSet<u64>,
+    /// Next node of the sweep in progress.
+    pub cursor: Option<NodeKey>,
+}
+
+impl PruneState {
+    pub fn is_retained(&self, version: u64) -> bool {
+        version == 0 || version >= self.floor || self.kept.contains(&version)
+    }
+
+    /// Whether an entry that is the newest one over versions `[from, until)` is read by a retained version.
+    fn covers(&self, from: u64, until: u64) -> bool {
+        until > self.floor || self.kept.range(from..until).next().is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PruneProgress {
+    /// Node entries deleted by this step.
+    pub removed: usize,
+    /// The sweep finished, nothing is left to prune under the current head.
+    pub done: bool,
+}
+
+/// In-memory `VersionedBackend`.
+#[derive(Debug, Clone, Default)]
+pub struct MemVersions {
+    pub entries: BTreeMap<VersionedKey, Option<Hash>>,
+    head: u64,
+    prune: PruneState,
+}
+
+impl VersionedBackend for MemVersions {
+    fn entry_prev(&self, key: &VersionedKey) -> Result<Option<(VersionedKey, Option<Hash>)>> {
+        Ok(self.entries.range(..*key).next_back().map(|(k, v)| (*k, *v)))
+    }
+
+    fn entry_next(&self, key: &VersionedKey) -> Result<Option<(VersionedKey, Option<Hash>)>> {
+        Ok(self.entries.range(*key..).next().map(|(k, v)| (*k, *v)))
+    }
+
+    fn put_entry(&mut self, key: VersionedKey, value: Option<Hash>) -> Result<()> {
+        self.entries.insert(key, value);
+        Ok(())
+    }
+
+    fn delete_entry(&mut self, key: &VersionedKey) -> Result<()> {
+        self.entries.remove(key);
+        Ok(())
+    }
+
+    fn head(&self) -> Result<u64> {
+        Ok(self.head)
+    }
+
+    fn set_head(&mut self, version: u64) -> Result<()> {
+        self.head = version;
+        Ok(())
+    }
+
+    fn prune_state(&self) -> Result<PruneState> {
+        Ok(self.prune.clone())
+    }
+
+    fn set_prune_state(&mut self, state: &PruneState) -> Result<()> {
+        self.prune = state.clone();
+        Ok(())
+    }
+}
+
+// ============================================================================
+// VERSION VIEW
+// ============================================================================
+
+/// Read-only `HubtRead` of the tree as of `version`: each node resolves to its
+/// newest entry at or below `version`.
+pub struct VersionView<'a, B> {
+    backend: &'a B,
+    version: u64,
+}
+
+impl<'a, B: VersionedBackend> VersionView<'a, B> {
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Live node following `cursor` (inclusive) in the direction of the search.
+    fn resolve(&self, mut cursor: VersionedKey, forward: bool) -> Result<Option<(NodeKey, Hash)>> {
+        loop {
+            let found = if forward { self.backend.entry_next(&cursor)? } else { self.backend.entry_prev(&cursor)? };
+            let node = match found {
+                None => return Ok(None),
+                Some((k, _)) => k.node,
+            };
+            if let Some(hash) = self.get(&node)? {
+                return Ok(Some((node, hash)));
+            }
+            // Skip every entry of this node, nothing sorts between (path, len) and (path, len + 1)
+            cursor = if forward {
+                VersionedKey { node: NodeKey { path: node.path, len: node.len + 1 }, version: 0 }
+            } else {
+                VersionedKey { node, version: 0 }
+            };
+        }
+    }
+}
+
+impl<'a, B: VersionedBackend> HubtRead for VersionView<'a, B> {
+    fn get(&self, key: &NodeKey) -> Result<Option<Hash>> {
+        let bound = VersionedKey { node: *key, version: self.version.saturating_add(1) };
+        match self.backend.entry_prev(&bound)? {
+            Some((k, v)) if k.node == *key => Ok(v),
+            _ => Ok(None),
+        }
+    }
+
+    fn seek_prev(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>> {
+        self.resolve(VersionedKey { node: *key, version: 0 }, false)
+    }
+
+    fn seek_next(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>> {
+        self.resolve(VersionedKey { node: *key, version: 0 }, true)
+    }
+}
+
+// ============================================================================
+// VERSIONED STORE
+// ============================================================================
+
+/// `HubtStore` that keeps every committed version.
+///
+/// Reads and writes go to the pending version `head + 1`, `commit` seals it.
+pub struct VersionedStore<B> {
+    pub backend: B,
+    head: u64,
+}
+
+impl VersionedStore<MemVersions> {
+    pub fn new(backend: MemVersions) -> Self {
+        Self { head: backend.head, backend }
+    }
+}
+
+impl<B: VersionedBackend> VersionedStore<B> {
+    pub fn open(backend: B) -> Result<Self> {
+        let head = backend.head()?;
+        Ok(Self { backend, head })
+    }
+
+    pub fn head(&self) -> u64 {
+        self.head
+    }
+
+    /// View of a committed version, version 0 is the empty tree.
+    pub fn at(&self, version: u64) -> Result<VersionView<'_, B>> {
+        if version > self.head {
+            return Err(HubtError::UnknownVersion(version));
+        }
+        if version < self.head && !self.backend.prune_state()?.is_retained(version) {
+            return Err(HubtError::Pruned(version));
+        }
+        Ok(VersionView { backend: &self.backend, version })
+    }
+
+    /// One incremental pruning step: drops the entries of up to `max_nodes` nodes that no
+    /// version retained by `policy` reads anymore.
+    ///
+    /// Versions are marked pruned (`HubtError::Pruned`) when a sweep starts, the sweep
+    /// itself resumes from the persisted cursor on the next call. Entries of the
+    /// pending version are never touched, so steps can be interleaved with writes.
+    pub fn prune(&mut self, policy: &PrunePolicy, max_nodes: usize) -> Result<PruneProgress> {
+        let mut state = self.backend.prune_state()?;
+        if state.cursor.is_none() {
+            let next = Self::next_prune_state(&state, policy, self.head);
+            if next == state {
+                return Ok(PruneProgress { removed: 0, done: true });
+            }
+            state = next;
+        }
+
+        let mut removed = 0;
+        let mut nodes = 0;
+        while let Some(cursor) = state.cursor {
+            if nodes == max_nodes {
+                break;
+            }
+            let node = match self.backend.entry_next(&VersionedKey { node: cursor, version: 0 })? {
+                Some((k, _)) => k.node,
+                None => {
+                    state.cursor = None;
+                    break;
+                }
+            };
+
+            let mut entries = Vec::new();
+            let mut at = VersionedKey { node, version: 0 };
+            while let Some((k, v)) = self.backend.entry_next(&at)? {
+                if k.node != node {
+                    break;
+                }
+                entries.push((k, v));
+                at = VersionedKey { node, version: k.version + 1 };
+            }
+
+            // A tombstone only matters while an older entry is still there to hide
+            let mut kept_any = false;
+            for (i, (k, v)) in entries.iter().enumerate() {
+                let until = entries.get(i + 1).map_or(u64::MAX, |(next, _)| next.version);
+                if state.covers(k.version, until) && (v.is_some() || kept_any) {
+                    kept_any = true;
+                } else {
+                    self.backend.delete_entry(k)?;
+                    removed += 1;
+                }
+            }
+
+            state.cursor = Some(NodeKey { path: node.path, len: node.len + 1 });
+            nodes += 1;
+        }
+
+        self.backend.set_prune_state(&state)?;
+        Ok(PruneProgress { removed, done: state.cursor.is_none() })
+    }
+
+    /// Retention after applying `policy` at `head`, with a fresh sweep cursor if anything changed.
+    fn next_prune_state(state: &PruneState, policy: &PrunePolicy, head: u64) -> PruneState {
+        let floor = match policy.keep_last {
+            Some(n) => max(state.floor, (head + 1).saturating_sub(max(n, 1))),
+            None => state.floor,
+        };
+        let mut kept: BTreeSet<u64> = state.kept.iter().copied().filter(|v| *v < floor && policy.retains(*v, head)).collect();
+        if let Some(k) = policy.checkpoint_every.filter(|k| *k > 0) {
+            kept.extend((state.floor.div_ceil(k) * k..floor).step_by(k as usize));
+        }
+        kept.extend(policy.pinned.range(state.floor..floor));
+        kept.remove(&0);
+
+        if floor == state.floor && kept == state.kept {
+            return state.clone();
+        }
+        PruneState { floor, kept, cursor: Some(NodeKey { path: [0u8; 32], len: 0 }) }
+    }
+
+    fn pending(&self) -> VersionView<'_, B> {
+        VersionView { backend: &self.backend, version: self.head + 1 }
+    }
+
+    fn commit(&mut self) -> Result<u64> {
+        self.backend.set_head(self.head + 1)?;
+        self.head += 1;
+        Ok(self.head)
+    }
+}
+
+impl<B: VersionedBackend> HubtRead for VersionedStore<B> {
+    fn get(&self, key: &NodeKey) -> Result<Option<Hash>> {
+        self.pending().get(key)
+    }
+
+    fn seek_prev(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>> {
+        self.pending().seek_prev(key)
+    }
+
+    fn seek_next(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>> {
+        self.pending().seek_next(key)
+    }
+}
+
+impl<B: VersionedBackend> HubtStore for VersionedStore<B> {
+    fn put(&mut self, key: NodeKey, hash: Hash) -> Result<()> {
+        self.backend.put_entry(VersionedKey { node: key, version: self.head + 1 }, Some(hash))
+    }
+
+    fn delete(&mut self, key: &NodeKey) -> Result<()> {
+        let entry = VersionedKey { node: *key, version: self.head + 1 };
+        let committed = VersionView { backend: &self.backend, version: self.head };
+        if committed.get(key)?.is_some() {
+            self.backend.put_entry(entry, None)
+        } else {
+            // Never committed, drop the pending write instead of leaving a tombstone
+            self.backend.delete_entry(&entry)
+        }
+    }
+}
+
+// ============================================================================
+// VERSIONED TREE
+// ============================================================================
+
+impl<B: VersionedBackend, H: TreeHasher> Tree<VersionedStore<B>, H> {
+    /// Latest committed version.
+    pub fn version(&self) -> u64 {
+        self.store.head()
+    }
+
+    /// Applies `ops` (together with any uncommitted `batch_update`) as the next version.
+    pub fn commit(&mut self, ops: Vec<Op>) -> Result<(u64, Hash)> {
+        self.batch_update(ops)?;
+        let version = self.store.commit()?;
+        Ok((version, self.root()?))
+    }
+
+    pub fn root_at(&self, version: u64) -> Result<Hash> {
+        self.at(version)?.root()
+    }
+
+    /// Proof of `k` against `root_at(version)`.
+    pub fn prove_at(&self, version: u64, k: Vec<u8>) -> Result<Proof> {
+        self.at(version)?.prove(k)
+    }
+
+    /// Leaves changed from `from` to `to`, see `Tree::diff`.
+    pub fn diff_at(&self, from: u64, to: u64) -> Result<Diff> {
+        self.at(from)?.diff(&self.at(to)?)
+    }
+
+    /// See `VersionedStore::prune`.
+    pub fn prune(&mut self, policy: &PrunePolicy, max_nodes: usize) -> Result<PruneProgress> {
+        self.store.prune(policy, max_nodes)
+    }
+
+    fn at(&self, version: u64) -> Result<Tree<VersionView<'_, B>, H>> {
+        Ok(Tree::with_store(self.store.at(version)?, self.mode()))
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hubt_mem::{Hubt, VersionedHubt};
+    use crate::proof::VerifyStatus;
+
+    fn batch(range: std::ops::Range<u32>, v: &[u8]) -> Vec<Op> {
+        range.map(|i| Op::Insert(i.to_be_bytes().to_vec(), v.to_vec())).collect()
+    }
+
+    #[test]
+    fn test_versions_match_replay() {
+        let batches = vec![
+            batch(0..100, b"a"),
+            batch(50..150, b"b"),
+            (0..150u32).step_by(4).map(|i| Op::Delete(i.to_be_bytes().to_vec())).collect(),
+            vec![],
+            batch(7..8, b"c"),
+        ];
+
+        let mut versioned = VersionedHubt::new();
+        let mut replay = Hubt::new();
+        let mut roots = vec![replay.root().unwrap()];
+        for (i, ops) in batches.into_iter().enumerate() {
+            replay.batch_update(ops.clone()).unwrap();
+            let (version, root) = versioned.commit(ops).unwrap();
+            assert_eq!(version, i as u64 + 1);
+            assert_eq!(root, replay.root().unwrap());
+            roots.push(root);
+        }
+
+        // Every past version is still intact after the later overwrites
+        for (version, root) in roots.iter().enumerate() {
+            assert_eq!(versioned.root_at(version as u64).unwrap(), *root);
+        }
+        assert_eq!(versioned.root_at(6), Err(HubtError::UnknownVersion(6)));
+
+        let k = 60u32.to_be_bytes().to_vec();
+        for (version, v, status) in [(1, b"b", VerifyStatus::Mismatch), (2, b"b", VerifyStatus::Included), (3, b"b", VerifyStatus::NonExistence)] {
+            let proof = versioned.prove_at(version, k.clone()).unwrap();
+            assert_eq!(proof.root, roots[version as usize]);
+            assert_eq!(Hubt::verify(&proof, k.clone(), v.to_vec()), status);
+        }
+        let k = 7u32.to_be_bytes().to_vec();
+        assert_eq!(Hubt::verify(&versioned.prove_at(2, k.clone()).unwrap(), k.clone(), b"a".to_vec()), VerifyStatus::Included);
+        assert_eq!(Hubt::verify(&versioned.prove_at(5, k.clone()).unwrap(), k, b"c".to_vec()), VerifyStatus::Included);
+    }
+
+    #[test]
+    fn test_uncommitted_writes_stay_pending() {
+        let mut versioned = VersionedHubt::new();
+        versioned.commit(batch(0..10, b"a")).unwrap();
+        let committed = versioned.root().unwrap();
+
+        versioned.batch_update(batch(10..20, b"a")).unwrap();
+        versioned.batch_update((10..20u32).map(|i| Op::Delete(i.to_be_bytes().to_vec())).collect()).unwrap();
+        assert_eq!(versioned.root().unwrap(), committed);
+        assert_eq!(versioned.root_at(1).unwrap(), committed);
+
+        // Nodes created and removed inside the pending version leave no entries behind
+        let entries = versioned.store.backend.entries.len();
+        let (version, root) = versioned.commit(vec![]).unwrap();
+        assert_eq!((version, root), (2, committed));
+        assert_eq!(versioned.store.backend.entries.len(), entries);
+    }
+
+    #[test]
+    fn test_prune_policy() {
+        let mut versioned = VersionedHubt::new();
+        let mut roots = vec![versioned.root().unwrap()];
+        let commit_range = |versioned: &mut VersionedHubt, roots: &mut Vec<Hash>, versions: std::ops::Range<u32>| {
+            for v in versions {
+                // Overwrite a shared set of keys so old versions own entries nobody else reads
+                let (_, root) = versioned.commit(batch(0..20, &v.to_be_bytes())).unwrap();
+                roots.push(root);
+            }
+        };
+        commit_range(&mut versioned, &mut roots, 1..13);
+
+        let policy = PrunePolicy { keep_last: Some(3), checkpoint_every: Some(4), pinned: [2].into() };
+        let before = versioned.store.backend.entries.len();
+        let mut steps = 0;
+        while !versioned.prune(&policy, 5).unwrap().done {
+            steps += 1;
+        }
+        assert!(steps > 1);
+        assert!(versioned.store.backend.entries.len() < before);
+        assert_eq!(versioned.prune(&policy, 5).unwrap(), PruneProgress { removed: 0, done: true });
+
+        let check = |versioned: &VersionedHubt, roots: &[Hash], retained: &[u64]| {
+            for version in 0..roots.len() as u64 {
+                if retained.contains(&version) {
+                    assert_eq!(versioned.root_at(version).unwrap(), roots[version as usize]);
+                    let k = 5u32.to_be_bytes().to_vec();
+                    let v = if version == 0 { vec![] } else { (version as u32).to_be_bytes().to_vec() };
+                    let status = if version == 0 { VerifyStatus::NonExistence } else { VerifyStatus::Included };
+                    assert_eq!(Hubt::verify(&versioned.prove_at(version, k.clone()).unwrap(), k, v), status);
+                } else {
+                    assert_eq!(versioned.root_at(version), Err(HubtError::Pruned(version)));
+                    assert_eq!(versioned.prove_at(version, b"k".to_vec()), Err(HubtError::Pruned(version)));
+                }
+            }
+        };
+        check(&versioned, &roots, &[0, 2, 4, 8, 10, 11, 12]);
+
+        // Later sweeps move the window, and pinning an already pruned version does not bring it back
+        commit_range(&mut versioned, &mut roots, 13..17);
+        let policy = PrunePolicy { pinned: [2, 3].into(), ..policy };
+        while !versioned.prune(&policy, 100).unwrap().done {}
+        check(&versioned, &roots, &[0, 2, 4, 8, 12, 14, 15, 16]);
+
+        // The head stays writable after pruning
+        let mut replay = Hubt::new();
+        replay.batch_update(batch(0..20, &16u32.to_be_bytes())).unwrap();
+        replay.batch_update(batch(20..30, b"x")).unwrap();
+        assert_eq!(versioned.commit(batch(20..30, b"x")).unwrap(), (17, replay.root().unwrap()));
+    }
+}
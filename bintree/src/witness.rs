@@ -0,0 +1,188 @@
+use crate::bits::prefix_match_be;
+use crate::error::{HubtError, Result};
+use crate::hasher::{Hash, KeyMode, TreeHasher};
+use crate::multiproof::MultiProof;
+use crate::store::{HubtRead, HubtStore, NodeKey};
+use crate::tree::Tree;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+
+/// The nodes of a witness, enough to run `batch_update` over the keys it was built for.
+///
+/// Subtrees the witness does not open are single opaque nodes with the subtree's hash. They are
+/// found as children like any node, and split points are placed against them as against their
+/// leaves: every key of the batch leaves an opaque subtree above its first differing bit.
+/// Reading a leaf under an opaque node is `NotInWitness`, so a batch touching a key the witness
+/// does not cover fails before writing anything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WitnessStore {
+    pub nodes: BTreeMap<NodeKey, Hash>,
+    opaque: BTreeSet<NodeKey>,
+}
+
+impl HubtRead for WitnessStore {
+    fn get(&self, key: &NodeKey) -> Result<Option<Hash>> {
+        if let Some(hash) = self.nodes.get(key) {
+            return Ok(Some(*hash));
+        }
+        // Opaque subtrees are disjoint, only the closest one before the leaf can hold it
+        let hidden = self.opaque.range(..=*key).next_back().filter(|node| prefix_match_be(&key.path, &node.path, node.len));
+        match hidden {
+            Some(_) if key.len == 256 => Err(HubtError::NotInWitness(key.path.to_vec())),
+            _ => Ok(None),
+        }
+    }
+
+    fn seek_prev(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>> {
+        Ok(self.nodes.range(..*key).next_back().map(|(k, v)| (*k, *v)))
+    }
+
+    fn seek_next(&self, key: &NodeKey) -> Result<Option<(NodeKey, Hash)>> {
+        Ok(self.nodes.range((Bound::Included(*key), Bound::Unbounded)).next().map(|(k, v)| (*k, *v)))
+    }
+
+    fn is_opaque(&self, key: &NodeKey) -> bool {
+        self.opaque.contains(key)
+    }
+}
+
+impl HubtStore for WitnessStore {
+    fn put(&mut self, key: NodeKey, hash: Hash) -> Result<()> {
+        self.nodes.insert(key, hash);
+        Ok(())
+    }
+
+    fn delete(&mut self, key: &NodeKey) -> Result<()> {
+        self.nodes.remove(key);
+        self.opaque.remove(key);
+        Ok(())
+    }
+}
+
+impl<S: HubtRead, H: TreeHasher> Tree<S, H> {
+    /// Witness for a block touching `keys` (read or written), see `Tree::from_witness`.
+    ///
+    /// It is the multiproof of the keys: the branches on their paths, the node each path ends on
+    /// and the hash of every subtree hanging off them.
+    pub fn witness(&self, keys: &[Vec<u8>]) -> Result<MultiProof> {
+        self.prove_many(keys)
+    }
+}
+
+impl<H: TreeHasher> Tree<WitnessStore, H> {
+    /// Partial tree over a verified `witness`. Running a batch over the witnessed keys gives the
+    /// same `BatchResult`, new root included, as the full tree.
+    pub fn from_witness(mode: KeyMode, witness: &MultiProof) -> Result<Self> {
+        let mut store = WitnessStore::default();
+        for (key, hash, opaque) in witness.revealed::<H>().ok_or(HubtError::InvalidWitness)? {
+            store.nodes.insert(key, hash);
+            if opaque {
+                store.opaque.insert(key);
+            }
+        }
+        Ok(Self::with_store(store, mode))
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::{get_bit_be, set_bit_be};
+    use crate::hasher::{Hardened, Sha256Hasher};
+    use crate::hubt_mem::{Hubt, PartialHubt};
+    use crate::multiproof::MultiStep;
+    use crate::test_util::key;
+    use crate::tree::Op;
+
+    /// Runs `ops` on the full tree and on a partial tree from its witness, returns the partial tree.
+    fn stateless(full: &mut Hubt, ops: Vec<Op>) -> PartialHubt {
+        let keys: Vec<Vec<u8>> = ops.iter().map(|op| op.key().to_vec()).collect();
+        let witness = full.witness(&keys).unwrap();
+        let mut partial = PartialHubt::<Sha256Hasher>::from_witness(KeyMode::Hashed, &witness).unwrap();
+        assert_eq!(partial.root().unwrap(), full.root().unwrap());
+        let expected = full.batch_update(ops.clone());
+        assert_eq!(partial.batch_update(ops), expected);
+        assert_eq!(partial.root().unwrap(), full.root().unwrap());
+        partial
+    }
+
+    #[test]
+    fn test_partial_tree_matches_full_tree() {
+        let mut full = Hubt::new();
+        full.batch_update((0..2000).map(|i| Op::Insert(key(i), b"v".to_vec())).collect()).unwrap();
+
+        // Inserts next to hidden subtrees, deletes that collapse branches, updates and misses
+        let mut seed = 7u64;
+        for round in 0..20u32 {
+            let mut ops = Vec::new();
+            for _ in 0..(1 + round * 3) {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let k = key((seed >> 33) as u32 % 4000);
+                ops.push(match seed % 4 {
+                    0 => Op::Delete(k),
+                    1 => Op::DeleteIfPresent(k),
+                    2 => Op::InsertIfAbsent(k, b"w".to_vec()),
+                    _ => Op::Insert(k, round.to_be_bytes().to_vec()),
+                });
+            }
+            // Keep keys distinct, a failed condition is compared as well
+            let mut seen = BTreeSet::new();
+            ops.retain(|op| seen.insert(op.key().to_vec()));
+            stateless(&mut full, ops);
+        }
+
+        // Down to a single leaf and back, from the empty tree
+        let mut small = Hubt::new();
+        stateless(&mut small, vec![Op::Insert(key(1), b"v".to_vec()), Op::Insert(key(2), b"v".to_vec())]);
+        stateless(&mut small, vec![Op::Delete(key(1)), Op::Insert(key(3), b"v".to_vec())]);
+        stateless(&mut small, vec![Op::Delete(key(2)), Op::Delete(key(3))]);
+        assert_eq!(small.root().unwrap(), Hubt::new().root().unwrap());
+    }
+
+    #[test]
+    fn test_witness_is_checked() {
+        let mut full = Hubt::new();
+        full.batch_update((0..500).map(|i| Op::Insert(key(i), b"v".to_vec())).collect()).unwrap();
+        let witness = full.witness(&[key(1), key(2)]).unwrap();
+
+        let mut forged = witness.clone();
+        forged.siblings[0][0] ^= 1;
+        assert!(matches!(PartialHubt::<Sha256Hasher>::from_witness(KeyMode::Hashed, &forged), Err(HubtError::InvalidWitness)));
+
+        // Keys outside the witness are refused before anything is written
+        let mut partial = PartialHubt::<Sha256Hasher>::from_witness(KeyMode::Hashed, &witness).unwrap();
+        let before = partial.store.clone();
+        let path = KeyMode::Hashed.path::<Sha256Hasher>(&key(3));
+        let ops = vec![Op::Insert(key(1), b"w".to_vec()), Op::Insert(key(3), b"w".to_vec())];
+        assert_eq!(partial.batch_update(ops), Err(HubtError::NotInWitness(path.to_vec())));
+        assert!(partial.store == before);
+
+        // A witness for no keys only carries the root
+        let mut empty = PartialHubt::<Sha256Hasher>::from_witness(KeyMode::Hashed, &full.witness(&[]).unwrap()).unwrap();
+        assert_eq!(empty.root().unwrap(), full.root().unwrap());
+        assert!(empty.batch_update(vec![Op::Delete(key(1))]).is_err());
+    }
+
+    #[test]
+    fn test_witness_can_not_hide_a_key() {
+        type H = Hardened<Sha256Hasher>;
+        let mut full = Hubt::<H>::with_mode(KeyMode::Hashed);
+        full.batch_update(vec![Op::Insert(key(1), b"v".to_vec()), Op::Insert(key(2), b"v".to_vec())]).unwrap();
+        let witness = full.witness(&[key(1)]).unwrap();
+
+        // Key 1's leaf moved next to key 2, with the branch moved to keep it on its side, would
+        // let `InsertIfAbsent` of key 2 through
+        let dir = witness.shape.iter().position(|s| *s == MultiStep::Found).unwrap() as u8 - 1;
+        let mut path = KeyMode::Hashed.path::<Sha256Hasher>(&key(2));
+        let last = get_bit_be(&path, 255);
+        set_bit_be(&mut path, 255, 1 - last);
+        let mut forged = witness;
+        forged.shape[0] = MultiStep::Branch((0..255).find(|&i| get_bit_be(&path, i) == dir).unwrap());
+        forged.found[0].path = path;
+        assert!(matches!(PartialHubt::<H>::from_witness(KeyMode::Hashed, &forged), Err(HubtError::InvalidWitness)));
+    }
+}
@@ -0,0 +1,77 @@
+pub type Path = [u8; 32];
+
+// ============================================================================
+// BIT HELPERS (Optimized & Inlined)
+// ============================================================================
+#[inline(always)]
+pub fn get_bit_be(data: &[u8], index: u16) -> u8 {
+    if index >= 256 { return 0; }
+    let byte_idx = (index >> 3) as usize;
+    let bit_offset = 7 - (index & 7);
+    (data[byte_idx] >> bit_offset) & 1
+}
+
+#[inline(always)]
+pub fn set_bit_be(data: &mut [u8], index: u16, val: u8) {
+    if index >= 256 { return; }
+    let byte_idx = (index >> 3) as usize;
+    let bit_offset = 7 - (index & 7);
+    if val == 1 {
+        data[byte_idx] |= 1 << bit_offset;
+    } else {
+        data[byte_idx] &= !(1 << bit_offset);
+    }
+}
+
+#[inline]
+pub fn mask_after_be(data: &mut [u8], len: u16) {
+    if len >= 256 { return; }
+    let byte_idx = (len >> 3) as usize;
+    let start_clean_bit = len;
+
+    for i in start_clean_bit..((byte_idx as u16 + 1) << 3) {
+        let off = 7 - (i & 7);
+        data[byte_idx] &= !(1 << off);
+    }
+    if byte_idx + 1 < 32 {
+        data[(byte_idx + 1)..].fill(0);
+    }
+}
+
+pub fn lcp_be(p1: &Path, p2: &Path) -> (Path, u16) {
+    let mut len = 0;
+    let mut byte_idx = 0;
+    while byte_idx < 32 && p1[byte_idx] == p2[byte_idx] {
+        len += 8;
+        byte_idx += 1;
+    }
+    if byte_idx < 32 {
+        for i in 0..8 {
+            let idx = (byte_idx * 8) + i;
+            if get_bit_be(p1, idx as u16) == get_bit_be(p2, idx as u16) {
+                len += 1;
+            } else {
+                break;
+            }
+        }
+    }
+    let mut prefix = *p1;
+    mask_after_be(&mut prefix, len);
+    (prefix, len)
+}
+
+#[inline]
+pub fn prefix_match_be(target: &Path, path: &Path, len: u16) -> bool {
+    let full_bytes = (len >> 3) as usize;
+    if target[..full_bytes] != path[..full_bytes] {
+        return false;
+    }
+    let rem = len & 7;
+    if rem > 0 {
+        let mask = 0xFF << (8 - rem);
+        if (target[full_bytes] & mask) != (path[full_bytes] & mask) {
+            return false;
+        }
+    }
+    true
+}
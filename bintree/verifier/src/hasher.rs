@@ -0,0 +1,150 @@
+use crate::bits::Path;
+use core::cmp::min;
+use core::marker::PhantomData;
+use sha2::{Digest, Sha256};
+
+pub type Hash = [u8; 32];
+pub const ZERO_HASH: Hash = [0u8; 32];
+
+/// The hash function a tree is built with.
+///
+/// `hash` derives key paths, `leaf` and `node` build leaves and internal nodes.
+/// By default they are `concat_and_hash` of `key || value` and `left || right`,
+/// see `Hardened` for domain separated ones that also bind each node to its position.
+pub trait TreeHasher {
+    /// True if `bind` commits to the position, false if it is the node itself.
+    const BINDS_POSITIONS: bool = false;
+
+    fn hash(data: &[u8]) -> Hash;
+    fn concat_and_hash(a: &[u8], b: &[u8]) -> Hash;
+
+    /// Hash of the concatenated `parts`. The default copies them into one buffer,
+    /// streaming hashers feed them one by one.
+    fn hash_parts(parts: &[&[u8]]) -> Hash {
+        Self::hash(&parts.concat())
+    }
+
+    #[inline]
+    fn leaf(k: &[u8], v: &[u8]) -> Hash {
+        Self::concat_and_hash(k, v)
+    }
+
+    #[inline]
+    fn node(left: &Hash, right: &Hash) -> Hash {
+        Self::concat_and_hash(left, right)
+    }
+
+    /// What parents and the root commit to for the node `hash` at `(path, len)`, `path` masked
+    /// after `len`. The node itself by default, so a proof's path and lengths are not part of
+    /// the root, see `Hardened`.
+    #[inline]
+    fn bind(_len: u16, _path: &Path, hash: &Hash) -> Hash {
+        *hash
+    }
+}
+
+pub const LEAF_TAG: u8 = 0x00;
+pub const NODE_TAG: u8 = 0x01;
+pub const BIND_TAG: u8 = 0x02;
+
+/// `H` with domain separated leaves and nodes:
+/// `leaf = hash(0x00 || len(k) || k || len(v) || v)` with 4 byte big endian lengths,
+/// `node = hash(0x01 || left || right)`, `bind = hash(0x02 || len || path || hash)`.
+///
+/// Plain leaves collide across k/v splits (`"ab" || "c"` and `"a" || "bc"`) and a 64 byte
+/// node preimage is also a valid leaf. Plain nodes do not commit to their position, so a proof
+/// can move a leaf or an ancestor without changing the root. Roots differ from `H`'s, so a tree
+/// picks one for good.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Hardened<H>(PhantomData<H>);
+
+impl<H: TreeHasher> TreeHasher for Hardened<H> {
+    const BINDS_POSITIONS: bool = true;
+
+    #[inline]
+    fn hash(data: &[u8]) -> Hash {
+        H::hash(data)
+    }
+
+    #[inline]
+    fn concat_and_hash(a: &[u8], b: &[u8]) -> Hash {
+        H::concat_and_hash(a, b)
+    }
+
+    #[inline]
+    fn hash_parts(parts: &[&[u8]]) -> Hash {
+        H::hash_parts(parts)
+    }
+
+    fn leaf(k: &[u8], v: &[u8]) -> Hash {
+        let (k_len, v_len) = ((k.len() as u32).to_be_bytes(), (v.len() as u32).to_be_bytes());
+        H::hash_parts(&[&[LEAF_TAG], &k_len, k, &v_len, v])
+    }
+
+    #[inline]
+    fn node(left: &Hash, right: &Hash) -> Hash {
+        H::hash_parts(&[&[NODE_TAG], left, right])
+    }
+
+    #[inline]
+    fn bind(len: u16, path: &Path, hash: &Hash) -> Hash {
+        H::hash_parts(&[&[BIND_TAG], &len.to_be_bytes(), path, hash])
+    }
+}
+
+/// How a key is mapped onto its 256-bit tree path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyMode {
+    /// `path = hash(key)`, keys are spread uniformly over the whole tree.
+    #[default]
+    Hashed,
+    /// `path = key[..8] || hash(key)[..24]`.
+    ///
+    /// The caller prefixes every key with its 8 byte hotpath namespace, so all keys of a
+    /// namespace live under one subtree and updates to it only dirty that subtree.
+    /// Keys shorter than 8 bytes are zero padded in the namespace part.
+    Namespaced,
+}
+
+impl KeyMode {
+    pub fn path<H: TreeHasher>(&self, k: &[u8]) -> Path {
+        match self {
+            KeyMode::Hashed => H::hash(k),
+            KeyMode::Namespaced => {
+                let mut path = [0u8; 32];
+                let ns_len = min(k.len(), 8);
+                path[..ns_len].copy_from_slice(&k[..ns_len]);
+                path[8..].copy_from_slice(&H::hash(k)[..24]);
+                path
+            }
+        }
+    }
+}
+
+/// SHA-256, the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256Hasher;
+
+impl TreeHasher for Sha256Hasher {
+    #[inline]
+    fn hash(data: &[u8]) -> Hash {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        hasher.finalize().into()
+    }
+
+    #[inline]
+    fn concat_and_hash(a: &[u8], b: &[u8]) -> Hash {
+        let mut hasher = Sha256::new();
+        hasher.update(a);
+        hasher.update(b);
+        hasher.finalize().into()
+    }
+
+    #[inline]
+    fn hash_parts(parts: &[&[u8]]) -> Hash {
+        let mut hasher = Sha256::new();
+        parts.iter().for_each(|part| hasher.update(part));
+        hasher.finalize().into()
+    }
+}
@@ -0,0 +1,15 @@
+//! Verification of HUBT proofs, without the tree engine and its storage.
+//!
+//! `no_std`, proofs are heap allocated but verifying one does not allocate with the
+//! streaming hashers (`Sha256Hasher`, `Hardened<Sha256Hasher>`).
+#![no_std]
+
+extern crate alloc;
+
+pub mod bits;
+pub mod hasher;
+pub mod proof;
+
+pub use bits::Path;
+pub use hasher::{Hardened, Hash, KeyMode, Sha256Hasher, TreeHasher};
+pub use proof::{Proof, ProofNode, VerifyStatus};
@@ -0,0 +1,194 @@
+use crate::bits::{get_bit_be, lcp_be, mask_after_be, Path};
+use crate::hasher::{Hash, KeyMode, TreeHasher, ZERO_HASH};
+use alloc::vec::Vec;
+
+/// A sibling on the way from the found node to the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProofNode {
+    pub hash: Hash,
+    pub direction: u8, // Side of the sibling (0 = left, 1 = right)
+    pub len: u16,      // Length of the ancestor that branches into us and this sibling
+}
+
+/// The Universal Proof Struct.
+///
+/// `path(key)` below is the tree's `KeyMode::path` and `hash` its `TreeHasher`, sha256 by default.
+/// `Hubt`, `RocksHubt` and the Elixir `HUBT.prove/1` produce this proof and share the verifier below.
+///
+/// - If `path` == path(key) and `hash` == hash(key, value): It's an Inclusion Proof.
+/// - If `path` == path(key) and `hash` != hash(key, value): It's a Mismatch Proof.
+/// - If `path` != path(key): It's a Non-Existence Proof (pointing to the node where path(key)
+///   leaves the tree). It is only accepted if path(key) diverges from `path` below the last
+///   ancestor and no ancestor branches at the divergence bit, so the key's side is empty.
+///
+/// `hash` is the found node's own hash and every sibling hash is bound to the sibling's
+/// position (`TreeHasher::bind`). The verifier binds the found node and each ancestor to
+/// `path` and their lengths. Under `Hardened` neither can then be moved without changing the
+/// root, plain hashers bind nothing and only the shape checks above apply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Proof {
+    pub root: Hash,
+    pub nodes: Vec<ProofNode>,
+    pub path: Path, // The path of the node actually found in the tree
+    pub hash: Hash, // The hash of the node actually found in the tree
+    pub len: u16,   // The length of the node actually found in the tree (256 for a leaf)
+    pub value: Option<Vec<u8>>, // The stored value of the key, for trees that keep values
+}
+
+#[derive(Debug, PartialEq)]
+pub enum VerifyStatus {
+    Included,       // Key exists and Value matches
+    Mismatch,       // Key exists but Value is different
+    NonExistence,   // Key does not exist
+    Invalid,        // The proof itself is mathematically invalid (bad root/chain)
+}
+
+/// Checks that `path` is canonical for `len`, ancestor lengths strictly shrink towards
+/// the root and every sibling sits on the opposite side of `path` at its ancestor.
+pub fn verify_structure(proof: &Proof) -> bool {
+    if proof.len > 256 {
+        return false;
+    }
+    let mut canonical = proof.path;
+    mask_after_be(&mut canonical, proof.len);
+    if canonical != proof.path {
+        return false;
+    }
+
+    let mut below = proof.len;
+    for node in &proof.nodes {
+        if node.len >= below || node.direction != 1 - get_bit_be(&proof.path, node.len) {
+            return false;
+        }
+        below = node.len;
+    }
+    true
+}
+
+impl Proof {
+    /// Verifies the proof and determines the relationship between the Key, Value, and the Tree.
+    pub fn verify<H: TreeHasher>(&self, mode: KeyMode, k: &[u8], v: &[u8]) -> VerifyStatus {
+        let target_path = mode.path::<H>(k);
+        let claimed_leaf_hash = H::leaf(k, v);
+
+        // 1. Shape Check: Are the lengths and directions consistent with the found path?
+        if !verify_structure(self) {
+            return VerifyStatus::Invalid;
+        }
+
+        // 2. Basic Integrity Check: Does the proof path/hash actually hash up to the Root?
+        if !self.verify_integrity::<H>() {
+            return VerifyStatus::Invalid;
+        }
+        if self.root == ZERO_HASH {
+            return VerifyStatus::NonExistence;
+        }
+
+        // 3. Interpret the result
+        if self.path == target_path && self.len == 256 {
+            // Path matches exactly.
+            if self.hash == claimed_leaf_hash {
+                VerifyStatus::Included
+            } else {
+                VerifyStatus::Mismatch
+            }
+        } else {
+            // Path does not match. This is a Non-Existence proof.
+            // The target must leave the found node's edge below the last ancestor (div_idx < len)
+            // and no ancestor may branch exactly at the divergence bit, else the target's side is occupied.
+            let (_, div_idx) = lcp_be(&target_path, &self.path);
+            let ambiguous = self.nodes.iter().any(|node| node.len == div_idx);
+            if div_idx < self.len && !ambiguous {
+                VerifyStatus::NonExistence
+            } else {
+                VerifyStatus::Invalid
+            }
+        }
+    }
+
+    /// Does the proof path/hash actually hash up to the Root, each node bound to its position?
+    pub fn verify_integrity<H: TreeHasher>(&self) -> bool {
+        if self.root == ZERO_HASH { return self.nodes.is_empty() && self.hash == ZERO_HASH; }
+
+        let found = H::bind(self.len, &self.path, &self.hash);
+        let calc = self.nodes.iter().fold(found, |acc, node| {
+            let hash = if node.direction == 0 {
+                H::node(&node.hash, &acc)
+            } else {
+                H::node(&acc, &node.hash)
+            };
+            let mut prefix = self.path;
+            mask_after_be(&mut prefix, node.len);
+            H::bind(node.len, &prefix, &hash)
+        });
+        calc == self.root
+    }
+}
+
+// ============================================================================
+// TESTS
+// ============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::set_bit_be;
+    use crate::hasher::{Hardened, Sha256Hasher};
+    use alloc::vec;
+
+    /// Proof of `k` in the two leaf tree {k: v, other: v}, built by hand.
+    fn two_leaves<H: TreeHasher>(k: &[u8], other: &[u8], v: &[u8]) -> Proof {
+        let (path, sibling) = (KeyMode::Hashed.path::<H>(k), KeyMode::Hashed.path::<H>(other));
+        let (prefix, len) = lcp_be(&path, &sibling);
+        let leaf = H::leaf(k, v);
+        let (bound, sibling_bound) = (H::bind(256, &path, &leaf), H::bind(256, &sibling, &H::leaf(other, v)));
+        let direction = 1 - get_bit_be(&path, len);
+        let root = if direction == 1 { H::node(&bound, &sibling_bound) } else { H::node(&sibling_bound, &bound) };
+        let nodes = vec![ProofNode { hash: sibling_bound, direction, len }];
+        Proof { root: H::bind(len, &prefix, &root), nodes, path, hash: leaf, len: 256, value: None }
+    }
+
+    fn check<H: TreeHasher>() {
+        let proof = two_leaves::<H>(b"a", b"b", b"v");
+        assert_eq!(proof.verify::<H>(KeyMode::Hashed, b"a", b"v"), VerifyStatus::Included);
+        assert_eq!(proof.verify::<H>(KeyMode::Hashed, b"a", b"w"), VerifyStatus::Mismatch);
+
+        // The leaf of "a" proves "c" absent when "c" leaves its edge below the branch
+        let c = (0u32..).map(|i| i.to_be_bytes()).find(|c| {
+            let (_, div) = lcp_be(&KeyMode::Hashed.path::<H>(c), &proof.path);
+            div > proof.nodes[0].len && div < 256
+        });
+        assert_eq!(proof.verify::<H>(KeyMode::Hashed, &c.unwrap(), b"v"), VerifyStatus::NonExistence);
+        assert_eq!(proof.verify::<H>(KeyMode::Hashed, b"b", b"v"), VerifyStatus::Invalid);
+
+        let mut tampered = proof.clone();
+        tampered.nodes[0].direction ^= 1;
+        assert_eq!(tampered.verify::<H>(KeyMode::Hashed, b"a", b"v"), VerifyStatus::Invalid);
+        let mut tampered = proof.clone();
+        tampered.hash[0] ^= 1;
+        assert_eq!(tampered.verify::<H>(KeyMode::Hashed, b"a", b"v"), VerifyStatus::Invalid);
+    }
+
+    #[test]
+    fn test_verify_hand_built_proofs() {
+        check::<Sha256Hasher>();
+        check::<Hardened<Sha256Hasher>>();
+    }
+
+    #[test]
+    fn test_hardened_binds_positions() {
+        type H = Hardened<Sha256Hasher>;
+        let proof = two_leaves::<H>(b"a", b"b", b"v");
+
+        // Moving the leaf next to "b" and the branch to a bit that fits its direction keeps the
+        // shape valid, but both positions are part of the root
+        let mut forged = proof;
+        forged.path = KeyMode::Hashed.path::<H>(b"b");
+        let last = get_bit_be(&forged.path, 255);
+        set_bit_be(&mut forged.path, 255, 1 - last);
+        let direction = forged.nodes[0].direction;
+        forged.nodes[0].len = (0..255).find(|&i| get_bit_be(&forged.path, i) != direction).unwrap();
+        assert!(verify_structure(&forged));
+        assert_eq!(forged.verify::<H>(KeyMode::Hashed, b"b", b"v"), VerifyStatus::Invalid);
+    }
+}
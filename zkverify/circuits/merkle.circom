@@ -1,117 +1,130 @@
 pragma circom 2.0.0;
 
-include "../node_modules/circomlib/circuits/sha256/sha256.circom";
+include "../node_modules/circomlib/circuits/poseidon.circom";
 include "../node_modules/circomlib/circuits/bitify.circom";
 
-// Helper template to select between two 256-bit values based on a selector
-template Mux256() {
-    signal input left[256];
-    signal input right[256];
-    signal input sel;  // 0 = output left, 1 = output right
-    signal output out[256];
-
-    for (var i = 0; i < 256; i++) {
-        out[i] <== left[i] + sel * (right[i] - left[i]);
+// Inclusion of a 32 byte key and value in a HUBT root built with the Rust `PoseidonHasher`
+// (circomlib Poseidon, plain, no position binding). Shared vector: bintree/fixtures/poseidon.txt
+
+// PoseidonHasher::hash of 32 bytes given as two 128 bit big endian halves:
+// acc = 32, acc = Poseidon(acc, bytes[0..31]), acc = Poseidon(acc, bytes[31])
+template Hash32() {
+    signal input hi;
+    signal input lo;
+    signal output out;
+
+    component hiBits = Num2Bits(128);
+    component loBits = Num2Bits(128);
+    hiBits.in <== hi;
+    loBits.in <== lo;
+
+    // Last byte of lo is the second chunk, the 15 above it end the first one
+    var last = 0;
+    var rest = 0;
+    var e = 1;
+    for (var i = 0; i < 8; i++) {
+        last += loBits.out[i] * e;
+        e = e + e;
     }
-}
-
-template MerkleProof() {
-    // Public inputs as field elements (more compact)
-    signal input rootHi;      // High 128 bits of root as field element
-    signal input rootLo;      // Low 128 bits of root as field element
-    signal input leafHi;      // High 128 bits of leaf as field element
-    signal input leafLo;      // Low 128 bits of leaf as field element
-
-    // Private inputs
-    signal input siblings[3][256]; // 3 siblings, each 256 bits
-    signal input indices[3];       // path indices (0 or 1)
-
-    // Convert public field elements back to bit arrays
-    component rootHiBits = Num2Bits(128);
-    component rootLoBits = Num2Bits(128);
-    component leafHiBits = Num2Bits(128);
-    component leafLoBits = Num2Bits(128);
-
-    rootHiBits.in <== rootHi;
-    rootLoBits.in <== rootLo;
-    leafHiBits.in <== leafHi;
-    leafLoBits.in <== leafLo;
-
-    // Reconstruct full 256-bit arrays (MSB first)
-    signal rootBits[256];
-    signal leafBits[256];
-
-    for (var i = 0; i < 128; i++) {
-        rootBits[i] <== rootHiBits.out[127-i];
-        rootBits[i+128] <== rootLoBits.out[127-i];
-        leafBits[i] <== leafHiBits.out[127-i];
-        leafBits[i+128] <== leafLoBits.out[127-i];
+    e = 1;
+    for (var i = 8; i < 128; i++) {
+        rest += loBits.out[i] * e;
+        e = e + e;
     }
 
-    // Hash the leaf value
-    component leafHash = Sha256(256);
-    for (var i = 0; i < 256; i++) {
-        leafHash.in[i] <== leafBits[i];
-    }
+    component first = Poseidon(2);
+    first.inputs[0] <== 32;
+    first.inputs[1] <== hi * (2 ** 120) + rest;
 
-    // Level 0: hash(leafHash, sibling[0])
-    component mux0_left = Mux256();
-    component mux0_right = Mux256();
-    for (var i = 0; i < 256; i++) {
-        mux0_left.left[i] <== leafHash.out[i];
-        mux0_left.right[i] <== siblings[0][i];
-        mux0_right.left[i] <== siblings[0][i];
-        mux0_right.right[i] <== leafHash.out[i];
-    }
-    mux0_left.sel <== indices[0];
-    mux0_right.sel <== indices[0];
+    component second = Poseidon(2);
+    second.inputs[0] <== first.out;
+    second.inputs[1] <== last;
+    out <== second.out;
+}
 
-    component hash0 = Sha256(512);
-    for (var i = 0; i < 256; i++) {
-        hash0.in[i] <== mux0_left.out[i];
-        hash0.in[i + 256] <== mux0_right.out[i];
-    }
+// A node hash split into the 16 byte limbs of its big endian encoding
+template Limbs() {
+    signal input in;
+    signal output hi;
+    signal output lo;
 
-    // Level 1: hash(hash0, sibling[1])
-    component mux1_left = Mux256();
-    component mux1_right = Mux256();
-    for (var i = 0; i < 256; i++) {
-        mux1_left.left[i] <== hash0.out[i];
-        mux1_left.right[i] <== siblings[1][i];
-        mux1_right.left[i] <== siblings[1][i];
-        mux1_right.right[i] <== hash0.out[i];
-    }
-    mux1_left.sel <== indices[1];
-    mux1_right.sel <== indices[1];
+    component bits = Num2Bits_strict();
+    bits.in <== in;
 
-    component hash1 = Sha256(512);
-    for (var i = 0; i < 256; i++) {
-        hash1.in[i] <== mux1_left.out[i];
-        hash1.in[i + 256] <== mux1_right.out[i];
-    }
-
-    // Level 2: hash(hash1, sibling[2])
-    component mux2_left = Mux256();
-    component mux2_right = Mux256();
-    for (var i = 0; i < 256; i++) {
-        mux2_left.left[i] <== hash1.out[i];
-        mux2_left.right[i] <== siblings[2][i];
-        mux2_right.left[i] <== siblings[2][i];
-        mux2_right.right[i] <== hash1.out[i];
+    var h = 0;
+    var l = 0;
+    var e = 1;
+    for (var i = 0; i < 128; i++) {
+        l += bits.out[i] * e;
+        if (i < 126) {
+            h += bits.out[i + 128] * e;
+        }
+        e = e + e;
     }
-    mux2_left.sel <== indices[2];
-    mux2_right.sel <== indices[2];
+    hi <== h;
+    lo <== l;
+}
 
-    component hash2 = Sha256(512);
-    for (var i = 0; i < 256; i++) {
-        hash2.in[i] <== mux2_left.out[i];
-        hash2.in[i + 256] <== mux2_right.out[i];
+template MerkleProof(depth) {
+    // Public: the root as a field element, key and value as 128 bit halves
+    signal input root;
+    signal input keyHi;
+    signal input keyLo;
+    signal input valueHi;
+    signal input valueLo;
+
+    // Private: the proof's siblings from the leaf up, padded with enabled = 0
+    signal input siblings[depth];
+    signal input directions[depth]; // Side of the sibling (0 = left)
+    signal input enabled[depth];
+
+    // PoseidonHasher::leaf = Poseidon(LEAF_TAG, hash(k), hash(v))
+    component k = Hash32();
+    component v = Hash32();
+    k.hi <== keyHi;
+    k.lo <== keyLo;
+    v.hi <== valueHi;
+    v.lo <== valueLo;
+
+    component leaf = Poseidon(3);
+    leaf.inputs[0] <== 0;
+    leaf.inputs[1] <== k.out;
+    leaf.inputs[2] <== v.out;
+
+    // PoseidonHasher::node = Poseidon(NODE_TAG, l_hi, l_lo, r_hi, r_lo)
+    signal acc[depth + 1];
+    signal left[depth];
+    signal right[depth];
+    component leftLimbs[depth];
+    component rightLimbs[depth];
+    component node[depth];
+    acc[0] <== leaf.out;
+
+    for (var i = 0; i < depth; i++) {
+        directions[i] * (1 - directions[i]) === 0;
+        enabled[i] * (1 - enabled[i]) === 0;
+        if (i > 0) {
+            enabled[i] * (1 - enabled[i - 1]) === 0;
+        }
+
+        left[i] <== siblings[i] + directions[i] * (acc[i] - siblings[i]);
+        right[i] <== acc[i] + directions[i] * (siblings[i] - acc[i]);
+        leftLimbs[i] = Limbs();
+        rightLimbs[i] = Limbs();
+        leftLimbs[i].in <== left[i];
+        rightLimbs[i].in <== right[i];
+
+        node[i] = Poseidon(5);
+        node[i].inputs[0] <== 1;
+        node[i].inputs[1] <== leftLimbs[i].hi;
+        node[i].inputs[2] <== leftLimbs[i].lo;
+        node[i].inputs[3] <== rightLimbs[i].hi;
+        node[i].inputs[4] <== rightLimbs[i].lo;
+
+        acc[i + 1] <== acc[i] + enabled[i] * (node[i].out - acc[i]);
     }
 
-    // Verify root matches by comparing bit arrays
-    for (var i = 0; i < 256; i++) {
-        rootBits[i] === hash2.out[i];
-    }
+    root === acc[depth];
 }
 
-component main {public [rootHi, rootLo, leafHi, leafLo]} = MerkleProof();
+component main {public [root, keyHi, keyLo, valueHi, valueLo]} = MerkleProof(32);
@@ -1,124 +1,67 @@
-const crypto = require("crypto");
 const snarkjs = require("snarkjs");
 const fs = require("fs");
-
-// Convert byte array to bit array (MSB first)
-function bytesToBits(bytes) {
-    const bits = [];
-    for (let i = 0; i < bytes.length; i++) {
-        for (let j = 7; j >= 0; j--) {
-            bits.push((bytes[i] >> j) & 1);
+const path = require("path");
+
+// Must match the depth in circuits/merkle.circom
+const DEPTH = 32;
+
+// Shared with the Rust tests, a HUBT inclusion proof under PoseidonHasher
+const VECTOR = path.join(__dirname, "../../bintree/fixtures/poseidon.txt");
+
+function readVector(file) {
+    const vector = { nodes: [] };
+    for (const line of fs.readFileSync(file, "utf8").split("\n")) {
+        if (line.startsWith("#") || line.trim() === "") continue;
+        const fields = line.trim().split(" ");
+        if (fields[0] === "node") {
+            vector.nodes.push({ direction: fields[1], hash: BigInt("0x" + fields[2]) });
+        } else {
+            vector[fields[0]] = fields[1];
         }
     }
-    return bits;
+    return vector;
 }
 
-// Convert 32-byte hash to two 128-bit field elements (hi, lo)
-function bytesToFieldElements(bytes) {
-    // Split into high 16 bytes and low 16 bytes
-    const hi = bytes.slice(0, 16);
-    const lo = bytes.slice(16, 32);
-
-    // Convert to BigInt (MSB first)
-    let hiNum = 0n;
-    let loNum = 0n;
-
-    for (let i = 0; i < 16; i++) {
-        hiNum = (hiNum << 8n) | BigInt(hi[i]);
-        loNum = (loNum << 8n) | BigInt(lo[i]);
-    }
-
-    return [hiNum.toString(), loNum.toString()];
-}
-
-// SHA-256 hash function
-function sha256(data) {
-    return crypto.createHash("sha256").update(data).digest();
-}
-
-// Hash two 32-byte values together
-function hashPair(left, right) {
-    const combined = Buffer.concat([left, right]);
-    return sha256(combined);
+// 32 bytes of hex as two 128-bit field elements (hi, lo)
+function halves(hex) {
+    if (hex.length !== 64) throw new Error("expected 32 bytes: " + hex);
+    return [BigInt("0x" + hex.slice(0, 32)).toString(), BigInt("0x" + hex.slice(32)).toString()];
 }
 
 async function generateTestProof() {
-    // Create 8 leaf values
-    const leaves = [];
-    for (let i = 0; i < 8; i++) {
-        const value = Buffer.alloc(32);
-        value.writeUInt32BE(1000 + i, 28); // Put value at the end
-        leaves.push(value);
-    }
-
-    // Hash each leaf to get leaf hashes
-    const level0 = leaves.map(l => sha256(l));
+    const vector = readVector(VECTOR);
+    if (vector.nodes.length > DEPTH) throw new Error("proof deeper than the circuit");
 
-    // Build tree level by level
-    const tree = [level0];
-
-    let currentLevel = level0;
-    for (let lvl = 0; lvl < 3; lvl++) {
-        const nextLevel = [];
-        for (let i = 0; i < currentLevel.length; i += 2) {
-            const left = currentLevel[i];
-            const right = currentLevel[i + 1];
-            nextLevel.push(hashPair(left, right));
-        }
-        tree.push(nextLevel);
-        currentLevel = nextLevel;
-    }
-
-    const root = tree[3][0];
-    const leafIndex = 5;
-    const leafValue = leaves[leafIndex];
-
-    // Collect siblings for the proof
-    const siblings = [];
-    let currentIndex = leafIndex;
-
-    for (let lvl = 0; lvl < 3; lvl++) {
-        const isRight = currentIndex % 2 === 1;
-        const siblingIndex = isRight ? currentIndex - 1 : currentIndex + 1;
-        const sibling = tree[lvl][siblingIndex];
-        siblings.push(sibling);
-        currentIndex = Math.floor(currentIndex / 2);
-    }
-
-    // Convert root and leaf to field elements (hi, lo pairs)
-    const [rootHi, rootLo] = bytesToFieldElements(root);
-    const [leafHi, leafLo] = bytesToFieldElements(leafValue);
-
-    // Convert to bit arrays for circuit (private inputs)
+    const [keyHi, keyLo] = halves(vector.key);
+    const [valueHi, valueLo] = halves(vector.value);
+    const padding = DEPTH - vector.nodes.length;
     const input = {
-        rootHi: rootHi,
-        rootLo: rootLo,
-        leafHi: leafHi,
-        leafLo: leafLo,
-        siblings: siblings.map(s => bytesToBits(s)),
-        indices: []
+        root: BigInt("0x" + vector.root).toString(),
+        keyHi,
+        keyLo,
+        valueHi,
+        valueLo,
+        siblings: vector.nodes.map(n => n.hash.toString()).concat(Array(padding).fill("0")),
+        directions: vector.nodes.map(n => n.direction).concat(Array(padding).fill("0")),
+        enabled: vector.nodes.map(() => "1").concat(Array(padding).fill("0"))
     };
 
-    // Calculate indices (path bits)
-    currentIndex = leafIndex;
-    for (let lvl = 0; lvl < 3; lvl++) {
-        const isRight = currentIndex % 2 === 1;
-        input.indices.push(isRight ? "1" : "0");
-        currentIndex = Math.floor(currentIndex / 2);
-    }
-
     fs.mkdirSync("build", { recursive: true });
     fs.writeFileSync("build/input.json", JSON.stringify(input, null, 2));
 
-    console.log("Test data generated:");
-    console.log("  Root:", root.toString("hex"));
-    console.log("  Leaf:", leafValue.toString("hex"));
-    console.log("  Leaf index:", leafIndex);
-    console.log("  Indices:", input.indices.join(", "));
-    console.log("  Public inputs: rootHi, rootLo, leafHi, leafLo (4 field elements)");
+    console.log("Test data from", VECTOR);
+    console.log("  Root:", vector.root);
+    console.log("  Key:", vector.key);
+    console.log("  Value:", vector.value);
+    console.log("  Siblings:", vector.nodes.length, "of", DEPTH);
+    console.log("  Public inputs: root, keyHi, keyLo, valueHi, valueLo (5 field elements)");
 
+    // Fails unless the circuit reaches the Rust root
     await snarkjs.wtns.calculate(input, "build/merkle_js/merkle.wasm", "build/witness.wtns");
     console.log("✓ Test data and witness generated");
 }
 
-generateTestProof().catch(console.error);
+generateTestProof().catch(e => {
+    console.error(e);
+    process.exit(1);
+});